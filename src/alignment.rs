@@ -0,0 +1,553 @@
+//! Inspection of BAM and CRAM headers to cross-check reference genome, flowcell and lane given
+//! in the metadata.
+
+use crate::files::invalid_data;
+use crate::validation::{Diagnostic, sequence_data_with_pointer};
+use crate::{File, FileType, Metadata, ReferenceGenome};
+use flate2::read::MultiGzDecoder;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+const BAM_MAGIC: &[u8; 4] = b"BAM\x01";
+const CRAM_MAGIC: &[u8; 4] = b"CRAM";
+
+/// Upper limit of the decompressed CRAM header block, far above the size of real SAM headers.
+const MAX_CRAM_HEADER_SIZE: usize = 256 * 1024 * 1024;
+
+/// Header of a BAM or CRAM file.
+#[derive(Debug, Default, PartialEq)]
+pub struct AlignmentHeader {
+    /// Plain SAM header text
+    pub text: String,
+
+    /// Reference sequences with name and length
+    pub references: Vec<(String, u64)>,
+}
+
+/// Read group given by a '@RG' header line.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReadGroup {
+    pub id: String,
+
+    /// Platform unit, usually '{flowcell}.{lane}' or '{flowcell}.{lane}.{barcode}'
+    pub platform_unit: Option<String>,
+
+    pub sample: Option<String>,
+}
+
+impl ReadGroup {
+    /// Returns flowcell and lane given in the platform unit or, if missing, in the read group ID.
+    pub fn flowcell_and_lane(&self) -> Option<(&str, u32)> {
+        let value = self.platform_unit.as_deref().unwrap_or(&self.id);
+        let mut parts = value.split(['.', ':', '_']);
+        let flowcell = parts.next()?;
+        let lane = parts.next()?.parse().ok()?;
+        Some((flowcell, lane))
+    }
+}
+
+impl AlignmentHeader {
+    /// Returns all read groups given in the header text.
+    pub fn read_groups(&self) -> Vec<ReadGroup> {
+        self.text
+            .lines()
+            .filter_map(|line| line.strip_prefix("@RG\t"))
+            .map(|line| {
+                let mut read_group = ReadGroup::default();
+                for (tag, value) in line.split('\t').filter_map(|field| field.split_once(':')) {
+                    match tag {
+                        "ID" => read_group.id = value.to_string(),
+                        "PU" => read_group.platform_unit = Some(value.to_string()),
+                        "SM" => read_group.sample = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                read_group
+            })
+            .collect()
+    }
+
+    /// Determines the reference genome the reads are aligned to.
+    pub fn reference_genome(&self) -> Option<ReferenceGenome> {
+        ReferenceGenome::detect(
+            self.references
+                .iter()
+                .map(|(name, length)| (name.as_str(), *length)),
+        )
+    }
+}
+
+/// Reads the header of a BAM file from the decompressed BAM stream.
+///
+/// # Errors
+///
+/// If the data cannot be read or is not BAM, an `io::Error` will be returned.
+pub fn read_bam_header<R: Read>(mut reader: R) -> io::Result<AlignmentHeader> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != *BAM_MAGIC {
+        return Err(invalid_data("not a BAM file"));
+    }
+
+    let text_length = read_u32(&mut reader)? as usize;
+    let text = read_string(&mut reader, text_length)?;
+
+    let reference_count = read_u32(&mut reader)?;
+    let mut references = vec![];
+    for _ in 0..reference_count {
+        let name_length = read_u32(&mut reader)? as usize;
+        let name = read_string(&mut reader, name_length)?;
+        let length = read_u32(&mut reader)? as u64;
+        references.push((name, length));
+    }
+
+    Ok(AlignmentHeader { text, references })
+}
+
+/// Reads the SAM header of a CRAM 2.x or 3.x file.
+///
+/// Only raw and gzip compressed header blocks are supported.
+///
+/// # Errors
+///
+/// If the data cannot be read or is not CRAM, an `io::Error` will be returned.
+pub fn read_cram_header<R: Read>(mut reader: R) -> io::Result<AlignmentHeader> {
+    let mut file_definition = [0; 26];
+    reader.read_exact(&mut file_definition)?;
+    if file_definition[..4] != *CRAM_MAGIC {
+        return Err(invalid_data("not a CRAM file"));
+    }
+    let major_version = file_definition[4];
+    if !(2..=3).contains(&major_version) {
+        return Err(invalid_data(&format!(
+            "unsupported CRAM version {major_version}"
+        )));
+    }
+
+    // Container header, the header block is the first block of the first container
+    let container_length = read_u32(&mut reader)? as usize;
+    for _ in 0..4 {
+        read_itf8(&mut reader)?;
+    }
+    for _ in 0..2 {
+        read_ltf8(&mut reader)?;
+    }
+    read_itf8(&mut reader)?;
+    let landmarks = read_itf8(&mut reader)?;
+    for _ in 0..landmarks {
+        read_itf8(&mut reader)?;
+    }
+    if major_version >= 3 {
+        read_u32(&mut reader)?;
+    }
+
+    let mut block_header = [0; 2];
+    reader.read_exact(&mut block_header)?;
+    read_itf8(&mut reader)?;
+    let compressed_size = read_itf8(&mut reader)? as usize;
+    let raw_size = read_itf8(&mut reader)? as usize;
+    if compressed_size > container_length.min(MAX_CRAM_HEADER_SIZE)
+        || raw_size > MAX_CRAM_HEADER_SIZE
+    {
+        return Err(invalid_data("invalid CRAM header block size"));
+    }
+    // Sizes are not trusted for allocation, a truncated file must not allocate the full size
+    let mut data = Vec::new();
+    reader.take(compressed_size as u64).read_to_end(&mut data)?;
+    if data.len() != compressed_size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let data = match block_header[0] {
+        0 => data,
+        1 => {
+            let mut raw = Vec::new();
+            MultiGzDecoder::new(data.as_slice())
+                .take(raw_size as u64)
+                .read_to_end(&mut raw)?;
+            raw
+        }
+        method => {
+            return Err(invalid_data(&format!(
+                "unsupported CRAM header block compression method {method}"
+            )));
+        }
+    };
+
+    let mut data = data.as_slice();
+    let text_length = read_u32(&mut data)? as usize;
+    let text = read_string(&mut data, text_length)?;
+    let references = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("@SQ\t"))
+        .filter_map(|line| {
+            let mut name = None;
+            let mut length = None;
+            for (tag, value) in line.split('\t').filter_map(|field| field.split_once(':')) {
+                match tag {
+                    "SN" => name = Some(value.to_string()),
+                    "LN" => length = value.parse().ok(),
+                    _ => {}
+                }
+            }
+            Some((name?, length?))
+        })
+        .collect();
+
+    Ok(AlignmentHeader { text, references })
+}
+
+/// Reads the header of a BAM or CRAM file, the format is determined by the file content.
+///
+/// # Errors
+///
+/// If the file cannot be read or is neither BAM nor CRAM, an `io::Error` will be returned.
+pub fn read_header_file(path: &Path) -> io::Result<AlignmentHeader> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let start = reader.fill_buf()?;
+    if start.starts_with(&[0x1f, 0x8b]) {
+        read_bam_header(MultiGzDecoder::new(reader))
+    } else if start.starts_with(CRAM_MAGIC) {
+        read_cram_header(reader)
+    } else {
+        Err(invalid_data("neither BAM nor CRAM"))
+    }
+}
+
+/// Compares the alignment header with the reference genome of the sequence data and with
+/// flowcell and lane of the `File` entry located at `pointer`.
+pub fn compare(
+    file: &File,
+    reference_genome: &ReferenceGenome,
+    header: &AlignmentHeader,
+    pointer: &str,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    match header.reference_genome() {
+        Some(detected) if detected != *reference_genome => {
+            diagnostics.push(Diagnostic::error(
+                "alignment-reference-mismatch",
+                pointer,
+                format!(
+                    "'{}' is aligned to {}, but reference genome is {}",
+                    file.file_path, detected, reference_genome
+                ),
+            ));
+        }
+        Some(_) => {}
+        None => diagnostics.push(Diagnostic::warning(
+            "alignment-unknown-reference",
+            pointer,
+            format!(
+                "reference genome of '{}' cannot be determined from its header",
+                file.file_path
+            ),
+        )),
+    }
+
+    if file.flowcell_id.is_none() && file.lane_id.is_none() {
+        return diagnostics;
+    }
+
+    let read_groups = header.read_groups();
+    if read_groups.is_empty() {
+        diagnostics.push(Diagnostic::warning(
+            "alignment-missing-read-group",
+            pointer,
+            format!("'{}' has no read groups", file.file_path),
+        ));
+        return diagnostics;
+    }
+
+    let units = read_groups
+        .iter()
+        .filter_map(ReadGroup::flowcell_and_lane)
+        .collect::<Vec<_>>();
+
+    if let Some(flowcell_id) = &file.flowcell_id
+        && !units.iter().any(|(flowcell, _)| flowcell == flowcell_id)
+    {
+        diagnostics.push(Diagnostic::error(
+            "alignment-flowcell-mismatch",
+            format!("{pointer}/flowcellId"),
+            format!(
+                "flowcell '{}' is not found in the read groups of '{}'",
+                flowcell_id, file.file_path
+            ),
+        ));
+    }
+
+    if let Some(lane_id) = &file.lane_id {
+        let lane = lane_id
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse::<u32>()
+            .ok();
+        let found = units.iter().any(|(flowcell, unit_lane)| {
+            Some(*unit_lane) == lane
+                && file
+                    .flowcell_id
+                    .as_ref()
+                    .is_none_or(|flowcell_id| flowcell == flowcell_id)
+        });
+        if !found {
+            diagnostics.push(Diagnostic::error(
+                "alignment-lane-mismatch",
+                format!("{pointer}/laneId"),
+                format!(
+                    "lane '{}' is not found in the read groups of '{}'",
+                    lane_id, file.file_path
+                ),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Reads the headers of all BAM files referenced in the metadata and reports mismatches with
+/// the reference genome and their `File` entries.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::alignment::check_alignment_files;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     for diagnostic in check_alignment_files(&metadata, Path::new("submission/files")) {
+///         println!("{}", diagnostic);
+///     }
+/// }
+/// ```
+pub fn check_alignment_files(metadata: &Metadata, files_dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for (pointer, _, sequence_data) in sequence_data_with_pointer(metadata) {
+        for (f, file) in sequence_data.files.iter().enumerate() {
+            if file.file_type != FileType::Bam {
+                continue;
+            }
+            let pointer = format!("{pointer}/files/{f}");
+            let Ok(path) = file.relative_file_path() else {
+                continue;
+            };
+            match read_header_file(&path.to_path(files_dir)) {
+                Ok(header) => diagnostics.extend(compare(
+                    file,
+                    &sequence_data.reference_genome,
+                    &header,
+                    &pointer,
+                )),
+                Err(err) => diagnostics.push(Diagnostic::error(
+                    "alignment-unreadable",
+                    pointer,
+                    format!("'{}' cannot be read: {}", file.file_path, err),
+                )),
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buffer = [0; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_string<R: Read>(reader: &mut R, length: usize) -> io::Result<String> {
+    let mut buffer = Vec::new();
+    reader.take(length as u64).read_to_end(&mut buffer)?;
+    if buffer.len() != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    String::from_utf8(buffer[..end].to_vec()).map_err(|err| invalid_data(&err.to_string()))
+}
+
+/// Reads a CRAM ITF8 encoded integer.
+fn read_itf8<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let first = read_u8(reader)? as u32;
+    let extra_bytes = (first as u8).leading_ones().min(4);
+    if extra_bytes == 4 {
+        let mut value = first & 0x0f;
+        for _ in 0..3 {
+            value = (value << 8) | read_u8(reader)? as u32;
+        }
+        return Ok((value << 4) | (read_u8(reader)? as u32 & 0x0f));
+    }
+    let mut value = first & (0xff >> (extra_bytes + 1));
+    for _ in 0..extra_bytes {
+        value = (value << 8) | read_u8(reader)? as u32;
+    }
+    Ok(value)
+}
+
+/// Reads a CRAM LTF8 encoded integer.
+fn read_ltf8<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let first = read_u8(reader)?;
+    let extra_bytes = first.leading_ones();
+    let mut value = if extra_bytes >= 7 {
+        0
+    } else {
+        (first & (0xff >> (extra_bytes + 1))) as u64
+    };
+    for _ in 0..extra_bytes {
+        value = (value << 8) | read_u8(reader)? as u64;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const SAM_HEADER: &str = "@HD\tVN:1.6\tSO:coordinate\n\
+        @SQ\tSN:chr1\tLN:248956422\n\
+        @SQ\tSN:chr2\tLN:242193529\n\
+        @RG\tID:rg1\tPU:HKJ3VDSX3.2.ATCACG\tSM:index\n";
+
+    fn bam() -> Vec<u8> {
+        let mut data = BAM_MAGIC.to_vec();
+        data.extend((SAM_HEADER.len() as u32).to_le_bytes());
+        data.extend(SAM_HEADER.as_bytes());
+        data.extend(2u32.to_le_bytes());
+        for (name, length) in [("chr1", 248_956_422u32), ("chr2", 242_193_529)] {
+            data.extend((name.len() as u32 + 1).to_le_bytes());
+            data.extend(name.as_bytes());
+            data.push(0);
+            data.extend(length.to_le_bytes());
+        }
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn itf8(value: u32) -> Vec<u8> {
+        match value {
+            0..0x80 => vec![value as u8],
+            0x80..0x4000 => vec![0x80 | (value >> 8) as u8, value as u8],
+            _ => vec![0xc0 | (value >> 16) as u8, (value >> 8) as u8, value as u8],
+        }
+    }
+
+    fn cram() -> Vec<u8> {
+        let size = itf8(SAM_HEADER.len() as u32 + 4);
+        cram_with_block_sizes(&size, &size)
+    }
+
+    /// Creates a CRAM file with the given ITF8 encoded sizes of the raw header block.
+    fn cram_with_block_sizes(compressed_size: &[u8], raw_size: &[u8]) -> Vec<u8> {
+        let mut block_data = (SAM_HEADER.len() as u32).to_le_bytes().to_vec();
+        block_data.extend(SAM_HEADER.as_bytes());
+
+        let mut block = vec![0, 0];
+        block.extend(itf8(0));
+        block.extend(compressed_size);
+        block.extend(raw_size);
+        block.extend(&block_data);
+        block.extend([0; 4]);
+
+        let mut data = CRAM_MAGIC.to_vec();
+        data.extend([3, 0]);
+        data.extend([0; 20]);
+        data.extend((block.len() as u32).to_le_bytes());
+        data.extend([0, 0, 0, 0, 0, 0, 1, 0]);
+        data.extend([0; 4]);
+        data.extend(block);
+        data
+    }
+
+    #[test]
+    fn should_read_bam_and_cram_header() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in [("a.bam", bam()), ("a.cram", cram())] {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+
+            let header = read_header_file(&path).unwrap();
+
+            assert_eq!(header.text, SAM_HEADER);
+            assert_eq!(header.references.len(), 2);
+            assert_eq!(header.reference_genome(), Some(ReferenceGenome::GrCh38));
+            assert_eq!(
+                header.read_groups()[0].flowcell_and_lane(),
+                Some(("HKJ3VDSX3", 2))
+            );
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_cram_block_sizes() {
+        let size = itf8(SAM_HEADER.len() as u32 + 4);
+        // 4 GiB, more than the container and the header limit
+        let bogus = [0xff, 0xff, 0xff, 0xff, 0x0f];
+
+        for cram in [
+            cram_with_block_sizes(&bogus, &size),
+            cram_with_block_sizes(&size, &bogus),
+        ] {
+            let err = read_cram_header(cram.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        // Within the limits, but larger than the remaining data
+        let mut cram = cram_with_block_sizes(&itf8(0x1000), &size);
+        cram[26..30].copy_from_slice(&0x2000u32.to_le_bytes());
+        let err = read_cram_header(cram.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn should_read_itf8_and_ltf8() {
+        for value in [0, 0x7f, 0x80, 0x3fff, 0x4000, 0x1f_ffff] {
+            assert_eq!(read_itf8(&mut itf8(value).as_slice()).unwrap(), value);
+        }
+        assert_eq!(
+            read_itf8(&mut [0xff, 0xff, 0xff, 0xff, 0x0f].as_slice()).unwrap(),
+            u32::MAX
+        );
+        assert_eq!(read_ltf8(&mut [0x81, 0x00].as_slice()).unwrap(), 0x100);
+    }
+
+    #[test]
+    fn should_report_reference_and_lane_mismatch() {
+        let file: File = serde_json::from_value(serde_json::json!({
+            "filePath": "a.bam",
+            "fileType": "bam",
+            "fileChecksum": "0358a9852adc88c77e7321ddfb07caf2e9911986a90ff76816be142f6f38122d",
+            "fileSizeInBytes": 1024,
+            "flowcellId": "HKJ3VDSX3",
+            "laneId": "L001"
+        }))
+        .unwrap();
+        let header = read_bam_header(MultiGzDecoder::new(bam().as_slice())).unwrap();
+
+        let diagnostics = compare(&file, &ReferenceGenome::GrCh37, &header, "/file");
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>(),
+            vec!["alignment-reference-mismatch", "alignment-lane-mismatch"]
+        );
+    }
+}
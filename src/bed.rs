@@ -0,0 +1,334 @@
+//! Analysis of BED target region files of panel and exome sequencing.
+
+use crate::files::open_decompressed;
+use crate::reference::canonical_contig_name;
+use crate::validation::{Diagnostic, sequence_data_with_pointer};
+use crate::{FileType, Metadata, ReferenceGenome};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+/// Maximum number of invalid lines reported individually for a single BED file.
+const MAX_REPORTED_LINES: usize = 10;
+
+/// A target region given by a BED line, using 0-based half-open coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub contig: String,
+
+    pub start: u64,
+
+    pub end: u64,
+}
+
+/// Content of a BED file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Bed {
+    /// All valid regions in order of appearance
+    pub regions: Vec<Region>,
+
+    /// Line number and reason of each line that is not a valid region
+    pub invalid_lines: Vec<(usize, String)>,
+}
+
+impl Bed {
+    /// Returns the number of bases covered by the target regions, overlapping regions are only
+    /// counted once.
+    pub fn target_size(&self) -> u64 {
+        self.merged_regions()
+            .iter()
+            .map(|region| region.end - region.start)
+            .sum()
+    }
+
+    /// Returns the regions sorted by contig and start with overlapping and adjacent regions
+    /// merged.
+    pub fn merged_regions(&self) -> Vec<Region> {
+        let mut regions = self.regions.iter().collect::<Vec<_>>();
+        regions.sort_by(|a, b| (&a.contig, a.start).cmp(&(&b.contig, b.start)));
+
+        let mut merged = Vec::<Region>::new();
+        for region in regions {
+            match merged.last_mut() {
+                Some(last) if last.contig == region.contig && region.start <= last.end => {
+                    last.end = last.end.max(region.end);
+                }
+                _ => merged.push(region.clone()),
+            }
+        }
+        merged
+    }
+
+    /// Returns the distinct contig names in order of appearance.
+    pub fn contigs(&self) -> Vec<&str> {
+        let mut contigs = Vec::<&str>::new();
+        for region in &self.regions {
+            if !contigs.contains(&region.contig.as_str()) {
+                contigs.push(&region.contig);
+            }
+        }
+        contigs
+    }
+}
+
+/// Parses BED data, ignoring comments, 'track' and 'browser' lines.
+///
+/// Lines that are not a valid region are collected in `Bed::invalid_lines`.
+///
+/// # Errors
+///
+/// If the data cannot be read, an `io::Error` will be returned.
+pub fn parse<R: BufRead>(reader: R) -> io::Result<Bed> {
+    let mut bed = Bed::default();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+
+        let columns = line
+            .split(['\t', ' '])
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        let region = match columns[..] {
+            [contig, start, end, ..] => match (start.parse::<u64>(), end.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start < end => Ok(Region {
+                    contig: contig.to_string(),
+                    start,
+                    end,
+                }),
+                (Ok(start), Ok(end)) => Err(format!("start {start} is not before end {end}")),
+                _ => Err(format!("invalid coordinates '{start}' and '{end}'")),
+            },
+            _ => Err("less than three columns".to_string()),
+        };
+
+        match region {
+            Ok(region) => bed.regions.push(region),
+            Err(message) => bed.invalid_lines.push((index + 1, message)),
+        }
+    }
+
+    Ok(bed)
+}
+
+/// Parses a plain or gzip compressed BED file.
+///
+/// # Errors
+///
+/// If the file cannot be read, an `io::Error` will be returned.
+pub fn parse_file(path: &Path) -> io::Result<Bed> {
+    parse(open_decompressed(path)?)
+}
+
+/// Checks the regions of a BED file against the reference genome: contigs must be known and use
+/// a consistent naming convention, regions must not exceed the contig length.
+pub fn check(bed: &Bed, reference_genome: &ReferenceGenome, pointer: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for (line, message) in bed.invalid_lines.iter().take(MAX_REPORTED_LINES) {
+        diagnostics.push(Diagnostic::error(
+            "bed-invalid-region",
+            pointer,
+            format!("line {line}: {message}"),
+        ));
+    }
+    if bed.invalid_lines.len() > MAX_REPORTED_LINES {
+        diagnostics.push(Diagnostic::error(
+            "bed-invalid-region",
+            pointer,
+            format!(
+                "{} more invalid lines",
+                bed.invalid_lines.len() - MAX_REPORTED_LINES
+            ),
+        ));
+    }
+
+    if bed.regions.is_empty() {
+        diagnostics.push(Diagnostic::error(
+            "bed-empty",
+            pointer,
+            "BED file contains no target regions",
+        ));
+        return diagnostics;
+    }
+
+    let contigs = bed
+        .contigs()
+        .into_iter()
+        .filter(|contig| !is_non_primary_contig(contig))
+        .collect::<Vec<_>>();
+
+    let prefixed = contigs.iter().filter(|c| c.starts_with("chr")).count();
+    if prefixed > 0 && prefixed < contigs.len() {
+        diagnostics.push(Diagnostic::warning(
+            "bed-contig-naming",
+            pointer,
+            "contig names are used both with and without 'chr' prefix",
+        ));
+    } else {
+        let expected_prefix = *reference_genome == ReferenceGenome::GrCh38;
+        if !contigs.is_empty() && (prefixed > 0) != expected_prefix {
+            diagnostics.push(Diagnostic::warning(
+                "bed-contig-naming",
+                pointer,
+                format!(
+                    "contig names {} 'chr' prefix, which is unusual for {}",
+                    if prefixed > 0 { "use" } else { "do not use" },
+                    reference_genome
+                ),
+            ));
+        }
+    }
+
+    for contig in contigs {
+        let Some(length) = reference_genome.contig_length(contig) else {
+            diagnostics.push(Diagnostic::error(
+                "bed-unknown-contig",
+                pointer,
+                format!("contig '{contig}' is not part of {reference_genome}"),
+            ));
+            continue;
+        };
+        let out_of_bounds = bed
+            .regions
+            .iter()
+            .filter(|region| region.contig == contig && region.end > length)
+            .count();
+        if out_of_bounds > 0 {
+            diagnostics.push(Diagnostic::error(
+                "bed-region-out-of-bounds",
+                pointer,
+                format!(
+                    "{out_of_bounds} regions exceed the length of contig '{contig}' in {reference_genome}"
+                ),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Parses all BED files referenced in the metadata and checks them against the reference genome
+/// of their sequence data.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::bed::check_bed_files;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     for diagnostic in check_bed_files(&metadata, Path::new("submission/files")) {
+///         println!("{}", diagnostic);
+///     }
+/// }
+/// ```
+pub fn check_bed_files(metadata: &Metadata, files_dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut parsed = HashMap::new();
+
+    for (pointer, _, sequence_data) in sequence_data_with_pointer(metadata) {
+        for (f, file) in sequence_data.files.iter().enumerate() {
+            if file.file_type != FileType::Bed {
+                continue;
+            }
+            let pointer = format!("{pointer}/files/{f}");
+            let Ok(path) = file.relative_file_path() else {
+                continue;
+            };
+            let bed = parsed
+                .entry(path.clone())
+                .or_insert_with(|| parse_file(&path.to_path(files_dir)));
+            match bed {
+                Ok(bed) => {
+                    diagnostics.extend(check(bed, &sequence_data.reference_genome, &pointer))
+                }
+                Err(err) => diagnostics.push(Diagnostic::error(
+                    "bed-unreadable",
+                    pointer,
+                    format!("'{}' cannot be read: {}", file.file_path, err),
+                )),
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Contigs outside of the primary assembly, which are not checked.
+fn is_non_primary_contig(contig: &str) -> bool {
+    let contig = canonical_contig_name(contig);
+    contig.contains('_')
+        || contig.starts_with("GL")
+        || contig.starts_with("KI")
+        || contig.starts_with("HLA")
+        || contig.starts_with("NC_")
+        || contig == "EBV"
+        || contig == "hs37d5"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BED: &str = "track name=targets
+chr1\t100\t200\tEXON1
+chr1\t150\t300
+chr1\t1000\t1100
+chrUn_KI270302v1\t0\t100
+chr1\t300\t200
+chr2 10 20
+chrZ\t10\t20
+chr1\t248956400\t248956500
+";
+
+    #[test]
+    fn should_parse_bed_and_calculate_target_size() {
+        let bed = parse(BED.as_bytes()).unwrap();
+
+        assert_eq!(bed.regions.len(), 7);
+        assert_eq!(
+            bed.invalid_lines,
+            vec![(6, "start 300 is not before end 200".to_string())]
+        );
+        assert_eq!(bed.target_size(), 200 + 100 + 100 + 10 + 10 + 100);
+    }
+
+    #[test]
+    fn should_check_contigs_against_reference_genome() {
+        let bed = parse(BED.as_bytes()).unwrap();
+
+        let diagnostics = check(&bed, &ReferenceGenome::GrCh38, "/bed");
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>(),
+            vec![
+                "bed-invalid-region",
+                "bed-region-out-of-bounds",
+                "bed-unknown-contig"
+            ]
+        );
+
+        let diagnostics = check(&bed, &ReferenceGenome::GrCh37, "/bed");
+        assert!(
+            diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.code == "bed-contig-naming")
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Command line tool to validate, format and inspect GRZ metadata files and submissions.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use mv64e_grz_dto::canonical::to_upstream_json;
+use mv64e_grz_dto::checksum::{ChecksumCache, verify_files, verify_files_with_cache};
+use mv64e_grz_dto::integrity::{IntegrityStatus, check_integrity};
+use mv64e_grz_dto::pedigree::Pedigree;
+use mv64e_grz_dto::report::ValidationReport;
+use mv64e_grz_dto::samplesheet::Pipeline;
+use mv64e_grz_dto::{Metadata, alignment, bed, fastq, value_name, vcf};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+#[derive(Parser)]
+#[command(name = "grz-metadata", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate metadata and exit with failure if errors are found
+    Validate {
+        /// Metadata file or submission directory
+        path: PathBuf,
+
+        /// Also inspect BED, BAM, VCF and FASTQ files of the submission
+        #[arg(long)]
+        deep: bool,
+
+        /// Submission files directory, defaults to 'files' of the submission directory
+        #[arg(long)]
+        files_dir: Option<PathBuf>,
+
+        /// Fail on warnings too
+        #[arg(long)]
+        strict: bool,
+
+        /// Output format of the diagnostics
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+
+    /// Print metadata pretty-printed in the field order of the upstream example
+    Fmt {
+        /// Metadata file or submission directory
+        path: PathBuf,
+
+        /// Check whether the file is formatted and exit with failure if not
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+
+        /// Overwrite the metadata file instead of printing it
+        #[arg(long)]
+        write: bool,
+    },
+
+    /// Show changes between two metadata documents, exit with failure if they differ
+    Diff {
+        /// Previous metadata file or submission directory
+        old: PathBuf,
+
+        /// New metadata file or submission directory
+        new: PathBuf,
+
+        /// Output format of the changes
+        #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+    },
+
+    /// Print donors, lab data, files and consents
+    Summary {
+        /// Metadata file or submission directory
+        path: PathBuf,
+    },
+
+    /// Print the family of the index patient in PED format
+    Ped {
+        /// Metadata file or submission directory
+        path: PathBuf,
+    },
+
+    /// Print a CSV samplesheet of the submission files for an analysis pipeline
+    Samplesheet {
+        /// Metadata file or submission directory
+        path: PathBuf,
+
+        /// Pipeline to create the samplesheet for
+        #[arg(long, value_enum)]
+        pipeline: PipelineArg,
+
+        /// Submission files directory, defaults to 'files' of the submission directory
+        #[arg(long)]
+        files_dir: Option<PathBuf>,
+    },
+
+    /// Verify size, checksum and integrity of all submission files
+    VerifyFiles {
+        /// Metadata file or submission directory
+        path: PathBuf,
+
+        /// Submission files directory, defaults to 'files' of the submission directory
+        #[arg(long)]
+        files_dir: Option<PathBuf>,
+
+        /// Checksum cache file to skip hashing unchanged files
+        #[arg(long)]
+        cache: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+    Sarif,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DiffFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PipelineArg {
+    Sarek,
+    Raredisease,
+}
+
+/// Paths of a submission given either by its metadata file or its directory.
+struct Submission {
+    metadata_path: PathBuf,
+
+    files_dir: Option<PathBuf>,
+}
+
+impl Submission {
+    /// Resolves `<dir>/metadata/metadata.json` and `<dir>/files` for a submission directory. For
+    /// a metadata file within a 'metadata' directory, the sibling 'files' directory is used.
+    fn resolve(path: &Path, files_dir: Option<PathBuf>) -> Submission {
+        if path.is_dir() {
+            return Submission {
+                metadata_path: path.join("metadata").join("metadata.json"),
+                files_dir: files_dir.or_else(|| Some(path.join("files"))),
+            };
+        }
+        let files_dir = files_dir.or_else(|| {
+            let parent = path.parent()?;
+            if parent.file_name()? == "metadata" {
+                Some(parent.parent()?.join("files"))
+            } else {
+                None
+            }
+        });
+        Submission {
+            metadata_path: path.to_path_buf(),
+            files_dir,
+        }
+    }
+
+    fn read(&self) -> Result<(String, Metadata), String> {
+        let json = fs::read_to_string(&self.metadata_path)
+            .map_err(|err| format!("{}: {}", self.metadata_path.display(), err))?;
+        let metadata = Metadata::from_str(&json)
+            .map_err(|err| format!("{}: {}", self.metadata_path.display(), err))?;
+        Ok((json, metadata))
+    }
+
+    fn files_dir(&self) -> Result<&Path, String> {
+        self.files_dir.as_deref().ok_or_else(|| {
+            "submission files directory unknown, use '--files-dir' or a submission directory"
+                .to_string()
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Validate {
+            path,
+            deep,
+            files_dir,
+            strict,
+            format,
+        } => validate(&Submission::resolve(&path, files_dir), deep, strict, format),
+        Command::Fmt { path, check, write } => fmt(&Submission::resolve(&path, None), check, write),
+        Command::Diff { old, new, format } => diff(
+            &Submission::resolve(&old, None),
+            &Submission::resolve(&new, None),
+            format,
+        ),
+        Command::Summary { path } => summary(&Submission::resolve(&path, None)),
+        Command::Ped { path } => ped(&Submission::resolve(&path, None)),
+        Command::Samplesheet {
+            path,
+            pipeline,
+            files_dir,
+        } => samplesheet(&Submission::resolve(&path, files_dir), pipeline),
+        Command::VerifyFiles {
+            path,
+            files_dir,
+            cache,
+        } => verify(&Submission::resolve(&path, files_dir), cache.as_deref()),
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(message) => {
+            eprintln!("error: {message}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn validate(
+    submission: &Submission,
+    deep: bool,
+    strict: bool,
+    format: Format,
+) -> Result<bool, String> {
+    let (json, metadata) = submission.read()?;
+
+    let mut diagnostics = metadata.validate();
+    if deep {
+        let files_dir = submission.files_dir()?;
+        diagnostics.extend(bed::check_bed_files(&metadata, files_dir));
+        diagnostics.extend(alignment::check_alignment_files(&metadata, files_dir));
+        diagnostics.extend(vcf::check_vcf_files(&metadata, files_dir));
+        diagnostics.extend(fastq::check_fastq_files(&metadata, files_dir));
+    }
+
+    let source = submission.metadata_path.to_string_lossy();
+    let report = ValidationReport::new(&source, Some(&json), diagnostics);
+    match format {
+        Format::Text => {
+            for entry in &report.diagnostics {
+                println!("{}", entry.diagnostic);
+            }
+            eprintln!(
+                "{} errors, {} warnings",
+                report.error_count, report.warning_count
+            );
+        }
+        Format::Json => println!("{}", report.to_json().map_err(|err| err.to_string())?),
+        Format::Sarif => println!("{}", report.to_sarif().map_err(|err| err.to_string())?),
+    }
+
+    Ok(report.valid && (!strict || report.warning_count == 0))
+}
+
+fn fmt(submission: &Submission, check: bool, write: bool) -> Result<bool, String> {
+    let (json, metadata) = submission.read()?;
+    let formatted = to_upstream_json(&metadata).map_err(|err| err.to_string())? + "\n";
+
+    if check {
+        let formatted_already = json == formatted;
+        if !formatted_already {
+            eprintln!("{} is not formatted", submission.metadata_path.display());
+        }
+        Ok(formatted_already)
+    } else if write {
+        fs::write(&submission.metadata_path, formatted)
+            .map_err(|err| format!("{}: {}", submission.metadata_path.display(), err))?;
+        Ok(true)
+    } else {
+        print!("{formatted}");
+        Ok(true)
+    }
+}
+
+fn ped(submission: &Submission) -> Result<bool, String> {
+    let (_, metadata) = submission.read()?;
+    let pedigree = Pedigree::from_metadata(&metadata).map_err(|err| err.to_string())?;
+    print!("{pedigree}");
+    Ok(true)
+}
+
+fn samplesheet(submission: &Submission, pipeline: PipelineArg) -> Result<bool, String> {
+    let (_, metadata) = submission.read()?;
+    let pipeline = match pipeline {
+        PipelineArg::Sarek => Pipeline::Sarek,
+        PipelineArg::Raredisease => Pipeline::Raredisease,
+    };
+    let csv = mv64e_grz_dto::samplesheet::samplesheet(&metadata, submission.files_dir()?, pipeline)
+        .map_err(|err| err.to_string())?;
+    print!("{csv}");
+    Ok(true)
+}
+
+fn diff(old: &Submission, new: &Submission, format: DiffFormat) -> Result<bool, String> {
+    let (_, old) = old.read()?;
+    let (_, new) = new.read()?;
+
+    let diff = mv64e_grz_dto::diff::diff(&old, &new).map_err(|err| err.to_string())?;
+    match format {
+        DiffFormat::Text => print!("{diff}"),
+        DiffFormat::Json => println!("{}", diff.to_json().map_err(|err| err.to_string())?),
+    }
+
+    Ok(diff.is_empty())
+}
+
+fn summary(submission: &Submission) -> Result<bool, String> {
+    let (_, metadata) = submission.read()?;
+    let case = &metadata.submission;
+
+    println!(
+        "Submission {} of {} ({}, {}, {})",
+        case.tan_g,
+        case.submission_date,
+        value_name(&case.submission_type),
+        value_name(&case.disease_type),
+        value_name(&case.genomic_study_type)
+    );
+    println!(
+        "Submitter {}, genomic data center {}, local case {}",
+        case.submitter_id, case.genomic_data_center_id, case.local_case_id
+    );
+    println!(
+        "Submission ID {}",
+        metadata.submission_id().map_err(|err| err.to_string())?
+    );
+
+    for donor in &metadata.donors {
+        println!();
+        println!(
+            "Donor {} ({}, {})",
+            donor.donor_pseudonym,
+            value_name(&donor.relation),
+            value_name(&donor.gender)
+        );
+
+        let scopes = donor
+            .mv_consent
+            .scope
+            .iter()
+            .map(|scope| {
+                format!(
+                    "{} {}",
+                    value_name(&scope.domain),
+                    value_name(&scope.scope_type)
+                )
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "  MV consent {}: {}",
+            donor.mv_consent.version,
+            scopes.join(", ")
+        );
+        println!("  Research consents: {}", donor.research_consents.len());
+
+        for lab_datum in &donor.lab_data {
+            println!(
+                "  Lab datum '{}': {} {} {}",
+                lab_datum.lab_data_name,
+                value_name(&lab_datum.sequence_type),
+                value_name(&lab_datum.sequence_subtype),
+                value_name(&lab_datum.library_type)
+            );
+            let Some(sequence_data) = &lab_datum.sequence_data else {
+                continue;
+            };
+            println!(
+                "    {} on {}, mean depth {}",
+                sequence_data.bioinformatics_pipeline_name,
+                sequence_data.reference_genome,
+                sequence_data.mean_depth_of_coverage
+            );
+            for file in &sequence_data.files {
+                println!(
+                    "    {:<6} {:>15} {}",
+                    value_name(&file.file_type),
+                    file.file_size_in_bytes,
+                    file.file_path
+                );
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+fn verify(submission: &Submission, cache_path: Option<&Path>) -> Result<bool, String> {
+    let (_, metadata) = submission.read()?;
+    let files_dir = submission.files_dir()?;
+
+    let results = match cache_path {
+        Some(cache_path) => {
+            let cache = ChecksumCache::load(cache_path).map_err(|err| err.to_string())?;
+            let results = verify_files_with_cache(&metadata, files_dir, &cache, |_| {});
+            cache.save(cache_path).map_err(|err| err.to_string())?;
+            results
+        }
+        None => verify_files(&metadata, files_dir, |_| {}),
+    };
+
+    // Results of both checks are in the order of the files in the metadata
+    let integrity = check_integrity(&metadata, files_dir, |_| {});
+
+    let mut all_ok = true;
+    for (result, integrity) in results.iter().zip(&integrity) {
+        let mut failures = vec![];
+        if !result.status.is_ok() {
+            failures.push(format!("{:?}", result.status));
+        }
+        // A missing or unreadable file has already been reported by the checksum verification
+        if matches!(integrity.status, IntegrityStatus::Corrupt(_))
+            || (result.status.is_ok() && !integrity.status.is_ok())
+        {
+            failures.push(format!("{:?}", integrity.status));
+        }
+
+        if failures.is_empty() {
+            println!("ok     {}", result.file_path);
+        } else {
+            all_ok = false;
+            println!("failed {}: {}", result.file_path, failures.join(", "));
+        }
+    }
+
+    Ok(all_ok)
+}
@@ -0,0 +1,401 @@
+//! Canonical JSON serialization of `Metadata` according to RFC 8785 (JSON Canonicalization
+//! Scheme) and pretty printing in the field order of the upstream example metadata.
+
+use crate::{Metadata, SerdeError};
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// Field order of objects in the upstream example metadata by the path of field names leading
+/// to the object, array indices are not part of the path.
+///
+/// Optional fields missing in the example are placed next to related fields.
+const FIELD_ORDER: &[(&str, &[&str])] = &[
+    ("", &["submission", "donors"]),
+    (
+        "submission",
+        &[
+            "submissionDate",
+            "submissionType",
+            "submitterId",
+            "tanG",
+            "localCaseId",
+            "genomicDataCenterId",
+            "clinicalDataNodeId",
+            "labName",
+            "genomicStudyType",
+            "genomicStudySubtype",
+            "coverageType",
+            "diseaseType",
+        ],
+    ),
+    (
+        "donors",
+        &[
+            "donorPseudonym",
+            "gender",
+            "relation",
+            "mvConsent",
+            "researchConsents",
+            "labData",
+        ],
+    ),
+    (
+        "donors/mvConsent",
+        &["presentationDate", "version", "scope"],
+    ),
+    ("donors/mvConsent/scope", &["type", "date", "domain"]),
+    (
+        "donors/researchConsents",
+        &[
+            "schemaVersion",
+            "presentationDate",
+            "scope",
+            "noScopeJustification",
+        ],
+    ),
+    (
+        "donors/labData",
+        &[
+            "labDataName",
+            "tissueOntology",
+            "tissueTypeId",
+            "tissueTypeName",
+            "sampleConservation",
+            "sequenceType",
+            "sequenceSubtype",
+            "fragmentationMethod",
+            "libraryType",
+            "libraryPrepKit",
+            "libraryPrepKitManufacturer",
+            "sequencerModel",
+            "sequencerManufacturer",
+            "kitName",
+            "kitManufacturer",
+            "enrichmentKitManufacturer",
+            "enrichmentKitDescription",
+            "barcode",
+            "sequencingLayout",
+            "tumorCellCount",
+            "sequenceData",
+            "sampleDate",
+        ],
+    ),
+    ("donors/labData/tissueOntology", &["name", "version"]),
+    ("donors/labData/tumorCellCount", &["count", "method"]),
+    (
+        "donors/labData/sequenceData",
+        &[
+            "bioinformaticsPipelineName",
+            "referenceGenome",
+            "bioinformaticsPipelineVersion",
+            "percentBasesAboveQualityThreshold",
+            "meanDepthOfCoverage",
+            "minCoverage",
+            "targetedRegionsAboveMinCoverage",
+            "nonCodingVariants",
+            "callerUsed",
+            "files",
+        ],
+    ),
+    (
+        "donors/labData/sequenceData/percentBasesAboveQualityThreshold",
+        &["minimumQuality", "percent"],
+    ),
+    (
+        "donors/labData/sequenceData/callerUsed",
+        &["name", "version"],
+    ),
+    (
+        "donors/labData/sequenceData/files",
+        &[
+            "filePath",
+            "fileType",
+            "checksumType",
+            "fileChecksum",
+            "fileSizeInBytes",
+            "readOrder",
+            "readLength",
+            "flowcellId",
+            "laneId",
+        ],
+    ),
+];
+
+/// Serializes the metadata to its RFC 8785 canonical form.
+///
+/// Object members are sorted by the UTF-16 code units of their names, numbers are formatted as
+/// in ECMAScript and there is no whitespace, so logically equal documents result in the same
+/// text.
+///
+/// # Example
+///
+/// ```
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::canonical::to_canonical_json;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let metadata = Metadata::from_str(JSON).unwrap();
+///     let canonical = to_canonical_json(&metadata).unwrap();
+///     assert!(canonical.starts_with("{\"donors\":[{\"donorPseudonym\""));
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If the metadata cannot be serialized, an `SerdeError` will be returned.
+pub fn to_canonical_json(metadata: &Metadata) -> Result<String, SerdeError> {
+    Ok(canonicalize(&metadata.to_value()?))
+}
+
+/// Serializes any JSON value to its RFC 8785 canonical form.
+pub fn canonicalize(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+/// Serializes the metadata to pretty-printed JSON with fields in the order of the upstream
+/// example metadata and numbers formatted as in the canonical form, e.g. '116993' instead of
+/// '116993.0'.
+///
+/// Fields unknown to the upstream example are appended in lexicographical order.
+///
+/// # Example
+///
+/// ```
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::canonical::to_upstream_json;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let metadata = Metadata::from_str(JSON).unwrap();
+///     assert_eq!(to_upstream_json(&metadata).unwrap() + "\n", JSON);
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If the metadata cannot be serialized, an `SerdeError` will be returned.
+pub fn to_upstream_json(metadata: &Metadata) -> Result<String, SerdeError> {
+    let mut out = String::new();
+    write_pretty(&metadata.to_value()?, "", 0, &mut out);
+    Ok(out)
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(fields) => {
+            let mut fields = fields.iter().collect::<Vec<_>>();
+            fields.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, (name, value)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(name, out);
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        _ => write_scalar(value, out),
+    }
+}
+
+fn write_pretty(value: &Value, path: &str, indent: usize, out: &mut String) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            out.push_str("{\n");
+            for (i, (name, value)) in ordered_fields(fields, path).into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+                push_indent(indent + 1, out);
+                write_string(name, out);
+                out.push_str(": ");
+                let path = if path.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{path}/{name}")
+                };
+                write_pretty(value, &path, indent + 1, out);
+            }
+            out.push('\n');
+            push_indent(indent, out);
+            out.push('}');
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+                push_indent(indent + 1, out);
+                write_pretty(item, path, indent + 1, out);
+            }
+            out.push('\n');
+            push_indent(indent, out);
+            out.push(']');
+        }
+        Value::Object(_) => out.push_str("{}"),
+        Value::Array(_) => out.push_str("[]"),
+        _ => write_scalar(value, out),
+    }
+}
+
+fn ordered_fields<'a>(fields: &'a Map<String, Value>, path: &str) -> Vec<(&'a String, &'a Value)> {
+    let order = FIELD_ORDER
+        .iter()
+        .find(|(object_path, _)| *object_path == path)
+        .map(|(_, order)| *order)
+        .unwrap_or_default();
+    let mut fields = fields.iter().collect::<Vec<_>>();
+    // Stable sort keeps unknown fields in lexicographical order of the map
+    fields.sort_by_key(|(name, _)| {
+        order
+            .iter()
+            .position(|known| known == name)
+            .unwrap_or(order.len())
+    });
+    fields
+}
+
+fn push_indent(indent: usize, out: &mut String) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_scalar(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+        Value::Number(number) => {
+            // Numbers are always representable as f64 without the arbitrary precision feature
+            write_number(number.as_f64().unwrap_or_default(), out);
+        }
+        Value::String(value) => write_string(value, out),
+        Value::Array(_) | Value::Object(_) => unreachable!("not a scalar value"),
+    }
+}
+
+/// Formats a number as ECMAScript `Number.prototype.toString()` does, see RFC 8785
+/// section 3.2.2.3.
+fn write_number(value: f64, out: &mut String) {
+    if value == 0.0 {
+        out.push('0');
+        return;
+    }
+    if value < 0.0 {
+        out.push('-');
+    }
+
+    // Shortest representation that round-trips, e.g. '1.024e2'
+    let formatted = format!("{:e}", value.abs());
+    let (mantissa, exponent) = formatted
+        .split_once('e')
+        .expect("exponential format contains 'e'");
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().expect("exponent is an integer") + 1;
+
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.push_str(&"0".repeat((n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.push_str(&"0".repeat(-n as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let _ = write!(out, "e{}{}", if n > 0 { '+' } else { '-' }, (n - 1).abs());
+    }
+}
+
+/// Writes a JSON string, escaping only quotation marks, backslashes and control characters.
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_canonicalize_according_to_rfc8785() {
+        let value = json!({
+            "numbers": [333333333.3333333, 1e30, 4.50, 2e-3, 0.000001, 1e-7, -0.0, 116993.0],
+            "string": "\u{20ac}$\u{f}\nA'B\"\\\\\"/",
+            "\u{1f600}": 1,
+            "\u{fb33}": 2,
+            "a": [true, null]
+        });
+
+        assert_eq!(
+            canonicalize(&value),
+            concat!(
+                "{\"a\":[true,null],",
+                "\"numbers\":[333333333.3333333,1e+30,4.5,0.002,0.000001,1e-7,0,116993],",
+                "\"string\":\"\u{20ac}$\\u000f\\nA'B\\\"\\\\\\\\\\\"/\",",
+                "\"\u{1f600}\":1,\"\u{fb33}\":2}"
+            )
+        );
+    }
+
+    #[test]
+    fn should_reproduce_upstream_example() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+
+        assert_eq!(to_upstream_json(&metadata).unwrap() + "\n", MTB_JSON);
+        assert_eq!(
+            Metadata::from_str(&to_canonical_json(&metadata).unwrap())
+                .unwrap()
+                .fingerprint()
+                .unwrap(),
+            metadata.fingerprint().unwrap()
+        );
+    }
+}
@@ -0,0 +1,591 @@
+//! Verification of submission files against the size and checksum given in their `File` entry.
+
+use crate::{ChecksumType, File, FileError, Metadata};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Progress of a single file while it is being hashed.
+#[derive(Debug)]
+pub struct Progress<'a> {
+    /// Path of the file as given in the metadata
+    pub file_path: &'a str,
+
+    /// Number of bytes hashed so far
+    pub bytes_processed: u64,
+
+    /// Size of the file on disk
+    pub total_bytes: u64,
+}
+
+/// Outcome of verifying a single file.
+#[derive(Debug, PartialEq)]
+pub enum FileStatus {
+    /// Size and checksum match the metadata
+    Ok,
+
+    /// The file does not exist in the submission files directory
+    Missing,
+
+    /// The size of the file on disk differs from `fileSizeInBytes`
+    SizeMismatch { expected: f64, actual: u64 },
+
+    /// The checksum of the file on disk differs from `fileChecksum`
+    ChecksumMismatch { expected: String, actual: String },
+
+    /// The file exists but could not be read
+    Unreadable(String),
+
+    /// `filePath` is absolute or refers to a parent directory, so the file has not been accessed
+    InvalidPath(String),
+}
+
+impl FileStatus {
+    pub fn is_ok(&self) -> bool {
+        *self == FileStatus::Ok
+    }
+}
+
+/// Verification result of a single `File` entry.
+#[derive(Debug)]
+pub struct FileVerificationResult {
+    /// Path of the file as given in the metadata
+    pub file_path: String,
+
+    pub status: FileStatus,
+}
+
+/// Verifies size and checksum of all files referenced in the metadata.
+///
+/// Files are hashed in parallel, each file referenced by more than one `File` entry is hashed
+/// only once. The `progress` callback is invoked repeatedly while a file is being read and may
+/// be called from multiple threads. The results are returned in the order the files appear in
+/// the metadata.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::checksum::verify_files;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     let results = verify_files(&metadata, Path::new("submission/files"), |progress| {
+///         println!("{}: {}/{}", progress.file_path, progress.bytes_processed, progress.total_bytes);
+///     });
+///
+///     for result in results.iter().filter(|result| !result.status.is_ok()) {
+///         println!("{}: {:?}", result.file_path, result.status);
+///     }
+/// }
+/// ```
+pub fn verify_files<P>(
+    metadata: &Metadata,
+    files_dir: &Path,
+    progress: P,
+) -> Vec<FileVerificationResult>
+where
+    P: Fn(&Progress) + Sync,
+{
+    verify(metadata, files_dir, None, progress)
+}
+
+/// Verifies size and checksum of all files referenced in the metadata like `verify_files`, but
+/// uses checksums from the given cache for files whose size and modification time did not change.
+///
+/// Newly calculated checksums are added to the cache, it is up to the caller to save the cache
+/// afterwards.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::checksum::{ChecksumCache, verify_files_with_cache};
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     let cache = ChecksumCache::load(Path::new("checksums.json")).unwrap();
+///     let results = verify_files_with_cache(&metadata, Path::new("submission/files"), &cache, |_| {});
+///     cache.save(Path::new("checksums.json")).unwrap();
+/// }
+/// ```
+pub fn verify_files_with_cache<P>(
+    metadata: &Metadata,
+    files_dir: &Path,
+    cache: &ChecksumCache,
+    progress: P,
+) -> Vec<FileVerificationResult>
+where
+    P: Fn(&Progress) + Sync,
+{
+    verify(metadata, files_dir, Some(cache), progress)
+}
+
+/// Persistent cache of file checksums.
+///
+/// Entries are keyed by the absolute path of a file and are only used as long as size and
+/// modification time of the file are unchanged.
+#[derive(Debug, Default)]
+pub struct ChecksumCache {
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+    size: u64,
+    modified: u128,
+    checksum_type: ChecksumType,
+    checksum: String,
+}
+
+impl ChecksumCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the cache from the given file. If the file does not exist, an empty cache is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// If the file exists but cannot be read or parsed, an `io::Error` will be returned.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(err),
+        };
+        let entries = serde_json::from_str(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Self {
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Saves the cache to the given file.
+    ///
+    /// The cache is written to a temporary file first, which then replaces the given file.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be written, an `io::Error` will be returned.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let content = {
+            let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+            serde_json::to_string(&*entries)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        };
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Returns the number of cached checksums.
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, key: &CacheKey, checksum_type: &ChecksumType) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        match entries.get(&key.path) {
+            Some(entry)
+                if entry.size == key.size
+                    && entry.modified == key.modified
+                    && entry.checksum_type == *checksum_type =>
+            {
+                Some(entry.checksum.clone())
+            }
+            Some(_) => {
+                entries.remove(&key.path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: CacheKey, checksum_type: ChecksumType, checksum: &str) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(
+                key.path,
+                CacheEntry {
+                    size: key.size,
+                    modified: key.modified,
+                    checksum_type,
+                    checksum: checksum.to_string(),
+                },
+            );
+    }
+}
+
+/// Identifies the content of a file by its canonical path, size and modification time
+#[derive(Debug, PartialEq)]
+struct CacheKey {
+    path: PathBuf,
+    size: u64,
+    modified: u128,
+}
+
+impl CacheKey {
+    fn of(path: &Path) -> io::Result<Self> {
+        let path = path.canonicalize()?;
+        let meta = fs::metadata(&path)?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        Ok(CacheKey {
+            path,
+            size: meta.len(),
+            modified,
+        })
+    }
+}
+
+fn verify<P>(
+    metadata: &Metadata,
+    files_dir: &Path,
+    cache: Option<&ChecksumCache>,
+    progress: P,
+) -> Vec<FileVerificationResult>
+where
+    P: Fn(&Progress) + Sync,
+{
+    let files = metadata.files().collect::<Vec<_>>();
+
+    let mut unique_files = Vec::<&File>::new();
+    for file in &files {
+        if !unique_files.iter().any(|f| f.file_path == file.file_path) {
+            unique_files.push(file);
+        }
+    }
+
+    let checksums = unique_files
+        .par_iter()
+        .map(|file| {
+            let Ok(path) = resolve(files_dir, file) else {
+                return (file.file_path.as_str(), None);
+            };
+            let needs_hashing = files
+                .iter()
+                .filter(|f| f.file_path == file.file_path)
+                .any(|f| size_matches(&path, f));
+            let checksum = if needs_hashing {
+                Some(hash_file(&path, file, cache, &progress))
+            } else {
+                None
+            };
+            (file.file_path.as_str(), checksum)
+        })
+        .collect::<HashMap<_, _>>();
+
+    files
+        .iter()
+        .map(|file| FileVerificationResult {
+            file_path: file.file_path.clone(),
+            status: file_status(
+                files_dir,
+                file,
+                checksums
+                    .get(file.file_path.as_str())
+                    .and_then(Option::as_ref),
+            ),
+        })
+        .collect()
+}
+
+/// Calculates the hex encoded SHA-256 checksum of the given reader.
+///
+/// The `on_read` callback receives the total number of bytes read so far.
+pub fn sha256<R, F>(mut reader: R, on_read: F) -> io::Result<String>
+where
+    R: Read,
+    F: Fn(u64),
+{
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut total = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buffer[..read]);
+        total += read as u64;
+        on_read(total);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Returns the path of the file within the files directory, rejecting absolute paths and paths
+/// leading out of the files directory.
+fn resolve(files_dir: &Path, file: &File) -> Result<PathBuf, FileError> {
+    Ok(file.relative_file_path()?.to_path(files_dir))
+}
+
+fn size_matches(path: &Path, file: &File) -> bool {
+    match fs::metadata(path) {
+        Ok(meta) => meta.len() as f64 == file.file_size_in_bytes,
+        Err(_) => false,
+    }
+}
+
+fn hash_file<P>(
+    path: &Path,
+    file: &File,
+    cache: Option<&ChecksumCache>,
+    progress: &P,
+) -> io::Result<String>
+where
+    P: Fn(&Progress) + Sync,
+{
+    let total_bytes = fs::metadata(path)?.len();
+
+    // The key is taken before hashing, a file modified meanwhile must not be cached with the
+    // checksum of its previous content
+    let key = cache.and_then(|_| CacheKey::of(path).ok());
+    if let (Some(cache), Some(key)) = (cache, &key)
+        && let Some(checksum) = cache.get(key, &ChecksumType::Sha256)
+    {
+        progress(&Progress {
+            file_path: &file.file_path,
+            bytes_processed: total_bytes,
+            total_bytes,
+        });
+        return Ok(checksum);
+    }
+
+    let reader = fs::File::open(path)?;
+    let checksum = match file.checksum_type {
+        Some(ChecksumType::Sha256) | None => sha256(reader, |bytes_processed| {
+            progress(&Progress {
+                file_path: &file.file_path,
+                bytes_processed,
+                total_bytes,
+            })
+        })?,
+    };
+
+    if let (Some(cache), Some(key)) = (cache, key)
+        && CacheKey::of(path).is_ok_and(|current| current == key)
+    {
+        cache.insert(key, ChecksumType::Sha256, &checksum);
+    }
+    Ok(checksum)
+}
+
+fn file_status(files_dir: &Path, file: &File, checksum: Option<&io::Result<String>>) -> FileStatus {
+    let path = match resolve(files_dir, file) {
+        Ok(path) => path,
+        Err(err) => return FileStatus::InvalidPath(err.to_string()),
+    };
+    let actual_size = match fs::metadata(path) {
+        Ok(meta) if meta.is_file() => meta.len(),
+        Ok(_) => return FileStatus::Unreadable("not a regular file".to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return FileStatus::Missing,
+        Err(err) => return FileStatus::Unreadable(err.to_string()),
+    };
+
+    if actual_size as f64 != file.file_size_in_bytes {
+        return FileStatus::SizeMismatch {
+            expected: file.file_size_in_bytes,
+            actual: actual_size,
+        };
+    }
+
+    match checksum {
+        Some(Ok(actual)) if actual.eq_ignore_ascii_case(&file.file_checksum) => FileStatus::Ok,
+        Some(Ok(actual)) => FileStatus::ChecksumMismatch {
+            expected: file.file_checksum.clone(),
+            actual: actual.clone(),
+        },
+        Some(Err(err)) => FileStatus::Unreadable(err.to_string()),
+        None => FileStatus::Unreadable("file has not been hashed".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_calculate_sha256() {
+        let checksum = sha256("abc".as_bytes(), |_| {}).unwrap();
+        assert_eq!(
+            checksum,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn should_verify_files() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let files_dir = tempfile::tempdir().unwrap();
+
+        let file = &mut metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files[0];
+        fs::write(files_dir.path().join(&file.file_path), "abc").unwrap();
+        file.file_size_in_bytes = 3.0;
+        file.file_checksum =
+            "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD".to_string();
+
+        let results = verify_files(&metadata, files_dir.path(), |_| {});
+
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[0].status, FileStatus::Ok);
+        assert_eq!(results[1].status, FileStatus::Missing);
+        assert_eq!(
+            results[2].status,
+            FileStatus::SizeMismatch {
+                expected: 116993.0,
+                actual: 3
+            }
+        );
+    }
+
+    #[test]
+    fn should_use_and_invalidate_cache() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let files_dir = tempfile::tempdir().unwrap();
+        let cache_file = files_dir.path().join("checksums.json");
+
+        let file = &mut metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files[0];
+        let path = files_dir.path().join(&file.file_path);
+        fs::write(&path, "abc").unwrap();
+        file.file_size_in_bytes = 3.0;
+        file.file_checksum =
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string();
+
+        let cache = ChecksumCache::new();
+        let results = verify_files_with_cache(&metadata, files_dir.path(), &cache, |_| {});
+        assert_eq!(results[0].status, FileStatus::Ok);
+        assert_eq!(cache.len(), 1);
+        cache.save(&cache_file).unwrap();
+
+        let cache = ChecksumCache::load(&cache_file).unwrap();
+        assert_eq!(
+            cache.get(&CacheKey::of(&path).unwrap(), &ChecksumType::Sha256),
+            Some(file_checksum(&metadata))
+        );
+
+        cache.insert(CacheKey::of(&path).unwrap(), ChecksumType::Sha256, "cached");
+        let results = verify_files_with_cache(&metadata, files_dir.path(), &cache, |_| {});
+        assert!(matches!(
+            &results[0].status,
+            FileStatus::ChecksumMismatch { actual, .. } if actual == "cached"
+        ));
+
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        let results = verify_files_with_cache(&metadata, files_dir.path(), &cache, |_| {});
+        assert_eq!(results[0].status, FileStatus::Ok);
+    }
+
+    #[test]
+    fn should_not_cache_files_modified_while_hashing() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let files_dir = tempfile::tempdir().unwrap();
+        let file = &metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_ref()
+            .unwrap()
+            .files[0];
+        let path = files_dir.path().join(&file.file_path);
+        fs::write(&path, "abc").unwrap();
+
+        let cache = ChecksumCache::new();
+        hash_file(&path, file, Some(&cache), &|_: &Progress| {
+            fs::write(&path, "modified").unwrap();
+        })
+        .unwrap();
+
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_not_access_files_outside_files_dir() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let files_dir = dir.path().join("files");
+        fs::create_dir(&files_dir).unwrap();
+        let outside = dir.path().join("outside.bed");
+        fs::write(&outside, "abc").unwrap();
+
+        let files = &mut metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files;
+        for (file, file_path) in files
+            .iter_mut()
+            .zip(["../outside.bed", outside.to_str().unwrap()])
+        {
+            file.file_path = file_path.to_string();
+            file.file_size_in_bytes = 3.0;
+            file.file_checksum =
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string();
+        }
+
+        let cache = ChecksumCache::new();
+        let results = verify_files_with_cache(&metadata, &files_dir, &cache, |progress| {
+            panic!("{} must not be read", progress.file_path)
+        });
+
+        assert!(matches!(results[0].status, FileStatus::InvalidPath(_)));
+        assert!(matches!(results[1].status, FileStatus::InvalidPath(_)));
+        assert!(cache.is_empty());
+    }
+
+    fn file_checksum(metadata: &Metadata) -> String {
+        metadata.files().next().unwrap().file_checksum.clone()
+    }
+}
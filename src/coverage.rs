@@ -0,0 +1,545 @@
+//! Computation of QC metrics from coordinate sorted BAM files to verify the metrics reported in
+//! the metadata.
+
+use crate::alignment::read_bam_header;
+use crate::bed::{self, Bed};
+use crate::files::invalid_data;
+use crate::metrics::SequenceMetrics;
+use crate::reference::canonical_contig_name;
+use crate::validation::{Diagnostic, sequence_data_with_pointer};
+use crate::{FileType, Metadata, SequenceData};
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Reads that are unmapped, secondary, QC failed, duplicates or supplementary are not counted for
+/// the depth of coverage, like in mosdepth and samtools depth.
+const DEPTH_EXCLUDED_FLAGS: u16 = 0x4 | 0x100 | 0x200 | 0x400 | 0x800;
+
+/// Secondary and supplementary alignments are not counted for base qualities.
+const QUALITY_EXCLUDED_FLAGS: u16 = 0x100 | 0x800;
+
+/// Upper limit of the size of a single BAM record, far above the size of records of long reads.
+const MAX_RECORD_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Relative difference of mean depth of coverage tolerated between reported and computed value.
+const MEAN_DEPTH_TOLERANCE: f64 = 0.1;
+
+/// Absolute difference of the fraction of targeted regions tolerated.
+const FRACTION_TOLERANCE: f64 = 0.05;
+
+/// Absolute difference in percentage points of bases above the quality threshold tolerated.
+const PERCENT_TOLERANCE: f64 = 5.0;
+
+/// Computes mean depth of coverage, the fraction of bases with at least `min_coverage` and the
+/// percentage of bases with at least `minimum_quality` from decompressed BAM data.
+///
+/// If target regions are given, coverage metrics are restricted to them, otherwise the whole
+/// genome given by the BAM header is used. Contig names of targets and reads are matched with
+/// and without 'chr' prefix.
+///
+/// # Errors
+///
+/// If the data cannot be read, is not BAM or not sorted by coordinate, an `io::Error` will be
+/// returned.
+pub fn compute_metrics<R: Read>(
+    mut reader: R,
+    targets: Option<&Bed>,
+    min_coverage: u32,
+    minimum_quality: u8,
+) -> io::Result<SequenceMetrics> {
+    let header = read_bam_header(&mut reader)?;
+
+    let mut coverage = Coverage::new(targets, min_coverage);
+    let size = match targets {
+        Some(targets) => targets.target_size(),
+        None => header.references.iter().map(|(_, length)| length).sum(),
+    };
+
+    let mut sweep = Sweep::default();
+    let mut current: Option<(i32, i32)> = None;
+    let (mut bases, mut bases_above_quality) = (0_u64, 0_u64);
+    let mut record = vec![];
+
+    while let Some(block_size) = read_block_size(&mut reader)? {
+        if block_size > MAX_RECORD_SIZE {
+            return Err(invalid_data(&format!(
+                "BAM record size {block_size} exceeds the maximum of {MAX_RECORD_SIZE}"
+            )));
+        }
+        record.resize(block_size as usize, 0);
+        reader.read_exact(&mut record)?;
+        if record.len() < 32 {
+            return Err(invalid_data("incomplete BAM record"));
+        }
+
+        let ref_id = i32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        let pos = i32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        let read_name_length = record[8] as usize;
+        let cigar_length = u16::from_le_bytes([record[12], record[13]]) as usize;
+        let flag = u16::from_le_bytes([record[14], record[15]]);
+        let sequence_length =
+            u32::from_le_bytes([record[16], record[17], record[18], record[19]]) as usize;
+
+        let cigar_start = 32 + read_name_length;
+        let quality_start = cigar_start + 4 * cigar_length + sequence_length.div_ceil(2);
+        if record.len() < quality_start + sequence_length {
+            return Err(invalid_data("incomplete BAM record"));
+        }
+
+        if flag & QUALITY_EXCLUDED_FLAGS == 0 {
+            let qualities = &record[quality_start..quality_start + sequence_length];
+            // 0xff indicates missing base qualities
+            if qualities.first() != Some(&0xff) {
+                bases += qualities.len() as u64;
+                bases_above_quality += qualities
+                    .iter()
+                    .filter(|quality| **quality >= minimum_quality)
+                    .count() as u64;
+            }
+        }
+
+        if ref_id < 0 || flag & DEPTH_EXCLUDED_FLAGS != 0 {
+            continue;
+        }
+        if pos < 0 {
+            return Err(invalid_data(&format!(
+                "mapped BAM record at invalid position {pos}"
+            )));
+        }
+
+        match current {
+            Some((current_ref_id, current_pos))
+                if ref_id < current_ref_id || (ref_id == current_ref_id && pos < current_pos) =>
+            {
+                return Err(invalid_data("BAM file is not sorted by coordinate"));
+            }
+            Some((current_ref_id, _)) if ref_id != current_ref_id => {
+                let contig = contig_name(&header.references, current_ref_id)?;
+                sweep.finish(|start, end, depth| coverage.add(contig, start, end, depth));
+            }
+            _ => {}
+        }
+        current = Some((ref_id, pos));
+
+        let contig = contig_name(&header.references, ref_id)?;
+        let mut start = pos as u64;
+        sweep.advance(start, |start, end, depth| {
+            coverage.add(contig, start, end, depth)
+        });
+        for op in record[cigar_start..cigar_start + 4 * cigar_length].chunks_exact(4) {
+            let op = u32::from_le_bytes([op[0], op[1], op[2], op[3]]);
+            let length = (op >> 4) as u64;
+            let end = || {
+                start
+                    .checked_add(length)
+                    .ok_or_else(|| invalid_data("BAM record exceeds the reference"))
+            };
+            match op & 0xf {
+                // M, = and X cover the reference
+                0 | 7 | 8 => {
+                    let end = end()?;
+                    sweep.add(start, end);
+                    start = end;
+                }
+                // D and N skip the reference
+                2 | 3 => start = end()?,
+                _ => {}
+            }
+        }
+    }
+
+    if let Some((ref_id, _)) = current {
+        let contig = contig_name(&header.references, ref_id)?;
+        sweep.finish(|start, end, depth| coverage.add(contig, start, end, depth));
+    }
+
+    if size == 0 {
+        return Err(invalid_data("no target regions or reference sequences"));
+    }
+    Ok(SequenceMetrics {
+        mean_depth_of_coverage: Some(coverage.depth_bases as f64 / size as f64),
+        min_coverage: Some(min_coverage as f64),
+        targeted_regions_above_min_coverage: Some(coverage.covered_bases as f64 / size as f64),
+        minimum_quality: (bases > 0).then_some(minimum_quality as f64),
+        percent_bases_above_quality_threshold: (bases > 0)
+            .then(|| 100.0 * bases_above_quality as f64 / bases as f64),
+    })
+}
+
+/// Computes the metrics of a BAM file, restricted to the regions of a BED file if given.
+///
+/// # Errors
+///
+/// If a file cannot be read, an `io::Error` will be returned. CRAM files are not supported, an
+/// `io::Error` of kind `Unsupported` will be returned.
+pub fn compute_metrics_file(
+    bam_path: &Path,
+    bed_path: Option<&Path>,
+    min_coverage: u32,
+    minimum_quality: u8,
+) -> io::Result<SequenceMetrics> {
+    let targets = bed_path.map(bed::parse_file).transpose()?;
+
+    let mut reader = BufReader::new(fs::File::open(bam_path)?);
+    if reader.fill_buf()?.starts_with(b"CRAM") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "computing metrics from CRAM files is not supported",
+        ));
+    }
+    compute_metrics(
+        BufReader::new(MultiGzDecoder::new(reader)),
+        targets.as_ref(),
+        min_coverage,
+        minimum_quality,
+    )
+}
+
+/// Compares the metrics reported in the sequence data located at `pointer` with computed metrics
+/// and warns about deviations exceeding the tolerance.
+pub fn compare(
+    sequence_data: &SequenceData,
+    metrics: &SequenceMetrics,
+    pointer: &str,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    if let Some(mean) = metrics.mean_depth_of_coverage
+        && (sequence_data.mean_depth_of_coverage - mean).abs() > MEAN_DEPTH_TOLERANCE * mean
+    {
+        diagnostics.push(Diagnostic::warning(
+            "coverage-mean-depth-mismatch",
+            format!("{pointer}/meanDepthOfCoverage"),
+            format!(
+                "reported mean depth of coverage {} differs from computed {:.1}",
+                sequence_data.mean_depth_of_coverage, mean
+            ),
+        ));
+    }
+
+    if let Some(fraction) = metrics.targeted_regions_above_min_coverage
+        && (sequence_data.targeted_regions_above_min_coverage - fraction).abs() > FRACTION_TOLERANCE
+    {
+        diagnostics.push(Diagnostic::warning(
+            "coverage-fraction-mismatch",
+            format!("{pointer}/targetedRegionsAboveMinCoverage"),
+            format!(
+                "reported fraction of targeted regions above {}x {} differs from computed {:.3}",
+                sequence_data.min_coverage,
+                sequence_data.targeted_regions_above_min_coverage,
+                fraction
+            ),
+        ));
+    }
+
+    if let Some(percent) = metrics.percent_bases_above_quality_threshold
+        && (sequence_data.percent_bases_above_quality_threshold.percent - percent).abs()
+            > PERCENT_TOLERANCE
+    {
+        diagnostics.push(Diagnostic::warning(
+            "coverage-quality-mismatch",
+            format!("{pointer}/percentBasesAboveQualityThreshold/percent"),
+            format!(
+                "reported percentage of bases above Q{} {} differs from computed {:.1}",
+                sequence_data
+                    .percent_bases_above_quality_threshold
+                    .minimum_quality,
+                sequence_data.percent_bases_above_quality_threshold.percent,
+                percent
+            ),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Computes the metrics of each sequence data from its BAM file, restricted to its BED file if
+/// given, and compares them with the reported metrics.
+///
+/// The minimum coverage and quality declared in the sequence data are used for the computation.
+/// Sequence data without BAM file or with a CRAM file are skipped. BAM files are processed in
+/// parallel.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::coverage::check_coverage;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     for diagnostic in check_coverage(&metadata, Path::new("submission/files")) {
+///         println!("{}", diagnostic);
+///     }
+/// }
+/// ```
+pub fn check_coverage(metadata: &Metadata, files_dir: &Path) -> Vec<Diagnostic> {
+    sequence_data_with_pointer(metadata)
+        .collect::<Vec<_>>()
+        .par_iter()
+        .flat_map(|(pointer, _, sequence_data)| {
+            let Some((f, bam)) = sequence_data
+                .files
+                .iter()
+                .enumerate()
+                .find(|(_, file)| file.file_type == FileType::Bam)
+            else {
+                return vec![];
+            };
+            let Ok(bam_path) = bam.relative_file_path() else {
+                return vec![];
+            };
+            let bed_path = sequence_data
+                .files
+                .iter()
+                .find(|file| file.file_type == FileType::Bed)
+                .and_then(|file| file.relative_file_path().ok())
+                .map(|path| path.to_path(files_dir));
+
+            let metrics = compute_metrics_file(
+                &bam_path.to_path(files_dir),
+                bed_path.as_deref(),
+                sequence_data.min_coverage.round() as u32,
+                sequence_data
+                    .percent_bases_above_quality_threshold
+                    .minimum_quality
+                    .round() as u8,
+            );
+            match metrics {
+                Ok(metrics) => compare(sequence_data, &metrics, pointer),
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => vec![],
+                Err(err) => vec![Diagnostic::error(
+                    "coverage-unreadable",
+                    format!("{pointer}/files/{f}"),
+                    format!("metrics of '{}' cannot be computed: {}", bam.file_path, err),
+                )],
+            }
+        })
+        .collect()
+}
+
+/// Sweep line over the aligned blocks of coordinate sorted reads of a single contig.
+#[derive(Default)]
+struct Sweep {
+    /// Depth changes at positions not yet passed
+    events: BTreeMap<u64, i64>,
+
+    depth: i64,
+
+    position: u64,
+}
+
+impl Sweep {
+    fn add(&mut self, start: u64, end: u64) {
+        *self.events.entry(start).or_default() += 1;
+        *self.events.entry(end).or_default() -= 1;
+    }
+
+    /// Emits all segments with constant, non-zero depth before `position`, which cannot change
+    /// anymore as no following read starts before.
+    fn advance<F: FnMut(u64, u64, u64)>(&mut self, position: u64, mut on_segment: F) {
+        while let Some(entry) = self.events.first_entry() {
+            if *entry.key() > position {
+                break;
+            }
+            let (event_position, delta) = entry.remove_entry();
+            if self.depth > 0 && event_position > self.position {
+                on_segment(self.position, event_position, self.depth as u64);
+            }
+            self.depth += delta;
+            self.position = event_position;
+        }
+    }
+
+    fn finish<F: FnMut(u64, u64, u64)>(&mut self, on_segment: F) {
+        self.advance(u64::MAX, on_segment);
+        self.depth = 0;
+    }
+}
+
+/// Merged target regions and index of the first region not yet passed by contig.
+type Targets = HashMap<String, (Vec<(u64, u64)>, usize)>;
+
+/// Accumulated depth of coverage of all bases or target bases.
+struct Coverage {
+    targets: Option<Targets>,
+
+    min_coverage: u64,
+
+    depth_bases: u64,
+
+    covered_bases: u64,
+}
+
+impl Coverage {
+    fn new(targets: Option<&Bed>, min_coverage: u32) -> Self {
+        let targets = targets.map(|targets| {
+            let mut by_contig = Targets::new();
+            for region in targets.merged_regions() {
+                by_contig
+                    .entry(canonical_contig_name(&region.contig).to_string())
+                    .or_default()
+                    .0
+                    .push((region.start, region.end));
+            }
+            by_contig
+        });
+        Coverage {
+            targets,
+            min_coverage: min_coverage as u64,
+            depth_bases: 0,
+            covered_bases: 0,
+        }
+    }
+
+    /// Adds a segment of constant depth, segments of a contig must be added in order.
+    fn add(&mut self, contig: &str, start: u64, end: u64, depth: u64) {
+        let Some(targets) = &mut self.targets else {
+            self.count(end - start, depth);
+            return;
+        };
+        let Some((regions, next)) = targets.get_mut(canonical_contig_name(contig)) else {
+            return;
+        };
+
+        while *next < regions.len() && regions[*next].1 <= start {
+            *next += 1;
+        }
+        let mut bases = 0;
+        for (region_start, region_end) in &regions[*next..] {
+            if *region_start >= end {
+                break;
+            }
+            bases += end.min(*region_end) - start.max(*region_start);
+        }
+        self.count(bases, depth);
+    }
+
+    fn count(&mut self, bases: u64, depth: u64) {
+        self.depth_bases += bases * depth;
+        if depth >= self.min_coverage {
+            self.covered_bases += bases;
+        }
+    }
+}
+
+fn read_block_size<R: Read>(reader: &mut R) -> io::Result<Option<u32>> {
+    let mut buffer = [0; 4];
+    match reader.read_exact(&mut buffer) {
+        Ok(()) => Ok(Some(u32::from_le_bytes(buffer))),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn contig_name(references: &[(String, u64)], ref_id: i32) -> io::Result<&str> {
+    references
+        .get(ref_id as usize)
+        .map(|(name, _)| name.as_str())
+        .ok_or_else(|| invalid_data(&format!("unknown reference sequence ID {ref_id}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reference ID, position, flag, CIGAR operations and base qualities of a record
+    type Record<'a> = (i32, i32, u16, &'a [(u32, u8)], &'a [u8]);
+
+    fn bam(references: &[(&str, u32)], records: &[Record]) -> Vec<u8> {
+        let mut data = b"BAM\x01".to_vec();
+        data.extend(0_u32.to_le_bytes());
+        data.extend((references.len() as u32).to_le_bytes());
+        for (name, length) in references {
+            data.extend((name.len() as u32 + 1).to_le_bytes());
+            data.extend(name.as_bytes());
+            data.push(0);
+            data.extend(length.to_le_bytes());
+        }
+        for (ref_id, pos, flag, cigar, qualities) in records {
+            let mut record = vec![];
+            record.extend(ref_id.to_le_bytes());
+            record.extend(pos.to_le_bytes());
+            record.extend([2, 60, 0, 0]);
+            record.extend((cigar.len() as u16).to_le_bytes());
+            record.extend(flag.to_le_bytes());
+            record.extend((qualities.len() as u32).to_le_bytes());
+            record.extend([0xff; 4]);
+            record.extend([0xff; 4]);
+            record.extend([0; 4]);
+            record.extend(b"r\0");
+            for (length, op) in cigar.iter() {
+                record.extend((length << 4 | *op as u32).to_le_bytes());
+            }
+            record.extend(vec![0; qualities.len().div_ceil(2)]);
+            record.extend(*qualities);
+            data.extend((record.len() as u32).to_le_bytes());
+            data.extend(record);
+        }
+        data
+    }
+
+    #[test]
+    fn should_compute_metrics_of_targets() {
+        let data = bam(
+            &[("chr1", 1000), ("chr2", 1000)],
+            &[
+                (0, 100, 0, &[(10, 0)], &[30; 10]),
+                (0, 105, 0, &[(5, 0), (10, 2), (5, 0)], &[20; 10]),
+                (0, 105, 0x400, &[(10, 0)], &[30; 10]),
+                (1, 0, 0, &[(10, 0)], &[30; 10]),
+            ],
+        );
+        let targets = bed::parse("1\t100\t120\n".as_bytes()).unwrap();
+
+        let metrics = compute_metrics(data.as_slice(), Some(&targets), 2, 30).unwrap();
+
+        // Depth 1 at 100-105, 2 at 105-110 and 0 at 110-120 due to the deletion
+        assert_eq!(metrics.mean_depth_of_coverage, Some(15.0 / 20.0));
+        assert_eq!(
+            metrics.targeted_regions_above_min_coverage,
+            Some(5.0 / 20.0)
+        );
+        assert_eq!(metrics.percent_bases_above_quality_threshold, Some(75.0));
+    }
+
+    #[test]
+    fn should_reject_unsorted_bam() {
+        let data = bam(
+            &[("chr1", 1000)],
+            &[
+                (0, 100, 0, &[(10, 0)], &[30; 10]),
+                (0, 50, 0, &[(10, 0)], &[30; 10]),
+            ],
+        );
+
+        assert!(compute_metrics(data.as_slice(), None, 20, 30).is_err());
+    }
+
+    #[test]
+    fn should_reject_mapped_record_at_negative_position() {
+        let data = bam(&[("chr1", 1000)], &[(0, -1, 0, &[(10, 0)], &[30; 10])]);
+
+        let err = compute_metrics(data.as_slice(), None, 20, 30).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn should_reject_bogus_record_size() {
+        let mut data = bam(&[("chr1", 1000)], &[]);
+        data.extend(u32::MAX.to_le_bytes());
+
+        let err = compute_metrics(data.as_slice(), None, 20, 30).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
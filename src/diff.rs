@@ -0,0 +1,365 @@
+//! Semantic diff between two `Metadata` documents, e.g. a correction or follow-up and the
+//! previous submission.
+
+use crate::{Metadata, SerdeError};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+
+/// Kind of a change between two documents.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+
+    Removed,
+
+    Changed,
+}
+
+/// A single added, removed or changed value.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub kind: ChangeKind,
+
+    /// Human-readable path using the keys of donors, lab data and files, e.g.
+    /// `donors[index_patient]/labData[Blut DNA normal]/sequenceData/meanDepthOfCoverage`
+    pub path: String,
+
+    /// JSON pointer to the value in the old document, missing for added values
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_pointer: Option<String>,
+
+    /// JSON pointer to the value in the new document, missing for removed values
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_pointer: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<Value>,
+}
+
+/// All changes between two metadata documents.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct MetadataDiff {
+    pub changes: Vec<Change>,
+}
+
+impl MetadataDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Serializes the changes to pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// If the serialization fails, an `SerdeError` will be returned.
+    pub fn to_json(&self) -> Result<String, SerdeError> {
+        serde_json::to_string_pretty(self).map_err(|err| SerdeError(err.to_string()))
+    }
+}
+
+impl Display for MetadataDiff {
+    /// Formats one change per line, prefixed by '+' for added, '-' for removed and '~' for
+    /// changed values.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            match (change.kind, &change.old_value, &change.new_value) {
+                (ChangeKind::Added, _, Some(new)) => writeln!(f, "+ {}: {}", change.path, new)?,
+                (ChangeKind::Removed, Some(old), _) => writeln!(f, "- {}: {}", change.path, old)?,
+                (_, Some(old), Some(new)) => writeln!(f, "~ {}: {} -> {}", change.path, old, new)?,
+                _ => writeln!(f, "~ {}", change.path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two metadata documents.
+///
+/// Donors are matched by `donor_pseudonym`, lab data by `lab_data_name` and files by
+/// `file_path`, so reordering these entries is not reported as change. Other lists are compared
+/// by position.
+///
+/// # Example
+///
+/// ```
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::diff::diff;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let previous = Metadata::from_str(JSON).unwrap();
+///     let mut correction = Metadata::from_str(JSON).unwrap();
+///     correction.submission.lab_name = "Other lab".to_string();
+///
+///     print!("{}", diff(&previous, &correction).unwrap());
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If one of the documents cannot be serialized, an `SerdeError` will be returned.
+pub fn diff(old: &Metadata, new: &Metadata) -> Result<MetadataDiff, SerdeError> {
+    let mut changes = vec![];
+    diff_values(
+        &old.to_value()?,
+        &new.to_value()?,
+        &Location::default(),
+        &Location::default(),
+        None,
+        &mut changes,
+    );
+    Ok(MetadataDiff { changes })
+}
+
+/// Human-readable path along with the JSON pointers to a value in both documents.
+#[derive(Default, Clone)]
+struct Location {
+    path: String,
+
+    pointer: String,
+}
+
+impl Location {
+    fn child(&self, path_segment: &str, pointer_segment: &str) -> Location {
+        let pointer_segment = pointer_segment.replace('~', "~0").replace('/', "~1");
+        Location {
+            path: if self.path.is_empty() {
+                path_segment.to_string()
+            } else if path_segment.starts_with('[') {
+                format!("{}{}", self.path, path_segment)
+            } else {
+                format!("{}/{}", self.path, path_segment)
+            },
+            pointer: format!("{}/{}", self.pointer, pointer_segment),
+        }
+    }
+}
+
+/// Returns the field identifying the entries of a list with the given name.
+pub(crate) fn key_field(name: &str) -> Option<&'static str> {
+    match name {
+        "donors" => Some("donorPseudonym"),
+        "labData" => Some("labDataName"),
+        "files" => Some("filePath"),
+        _ => None,
+    }
+}
+
+fn diff_values(
+    old: &Value,
+    new: &Value,
+    old_location: &Location,
+    new_location: &Location,
+    name: Option<&str>,
+    changes: &mut Vec<Change>,
+) {
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            for (name, old_value) in old_fields {
+                let old_child = old_location.child(name, name);
+                match new_fields.get(name) {
+                    Some(new_value) => diff_values(
+                        old_value,
+                        new_value,
+                        &old_child,
+                        &new_location.child(name, name),
+                        Some(name),
+                        changes,
+                    ),
+                    None => changes.push(removed(&old_child, old_value)),
+                }
+            }
+            for (name, new_value) in new_fields {
+                if !old_fields.contains_key(name) {
+                    changes.push(added(&new_location.child(name, name), new_value));
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items)) => match name.and_then(key_field) {
+            Some(key) => diff_keyed(
+                old_items,
+                new_items,
+                key,
+                old_location,
+                new_location,
+                changes,
+            ),
+            None => {
+                for (i, old_value) in old_items.iter().enumerate() {
+                    let index = i.to_string();
+                    let old_child = old_location.child(&format!("[{i}]"), &index);
+                    match new_items.get(i) {
+                        Some(new_value) => diff_values(
+                            old_value,
+                            new_value,
+                            &old_child,
+                            &new_location.child(&format!("[{i}]"), &index),
+                            None,
+                            changes,
+                        ),
+                        None => changes.push(removed(&old_child, old_value)),
+                    }
+                }
+                for (i, new_value) in new_items.iter().enumerate().skip(old_items.len()) {
+                    let child = new_location.child(&format!("[{i}]"), &i.to_string());
+                    changes.push(added(&child, new_value));
+                }
+            }
+        },
+        _ if old != new => changes.push(Change {
+            kind: ChangeKind::Changed,
+            path: old_location.path.clone(),
+            old_pointer: Some(old_location.pointer.clone()),
+            new_pointer: Some(new_location.pointer.clone()),
+            old_value: Some(old.clone()),
+            new_value: Some(new.clone()),
+        }),
+        _ => {}
+    }
+}
+
+/// Compares list entries matched by the value of their key field. Entries with the same key are
+/// matched in order of appearance.
+fn diff_keyed(
+    old_items: &[Value],
+    new_items: &[Value],
+    key: &str,
+    old_location: &Location,
+    new_location: &Location,
+    changes: &mut Vec<Change>,
+) {
+    let key_of = |item: &Value| {
+        item.get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut matched = vec![false; new_items.len()];
+
+    for (i, old_value) in old_items.iter().enumerate() {
+        let old_key = key_of(old_value);
+        let old_child = old_location.child(&format!("[{old_key}]"), &i.to_string());
+        let position = new_items
+            .iter()
+            .enumerate()
+            .position(|(j, new_value)| !matched[j] && key_of(new_value) == old_key);
+        match position {
+            Some(j) => {
+                matched[j] = true;
+                diff_values(
+                    old_value,
+                    &new_items[j],
+                    &old_child,
+                    &new_location.child(&format!("[{old_key}]"), &j.to_string()),
+                    None,
+                    changes,
+                );
+            }
+            None => changes.push(removed(&old_child, old_value)),
+        }
+    }
+
+    for (j, new_value) in new_items.iter().enumerate() {
+        if !matched[j] {
+            let child = new_location.child(&format!("[{}]", key_of(new_value)), &j.to_string());
+            changes.push(added(&child, new_value));
+        }
+    }
+}
+
+fn added(location: &Location, value: &Value) -> Change {
+    Change {
+        kind: ChangeKind::Added,
+        path: location.path.clone(),
+        old_pointer: None,
+        new_pointer: Some(location.pointer.clone()),
+        old_value: None,
+        new_value: Some(value.clone()),
+    }
+}
+
+fn removed(location: &Location, value: &Value) -> Change {
+    Change {
+        kind: ChangeKind::Removed,
+        path: location.path.clone(),
+        old_pointer: Some(location.pointer.clone()),
+        new_pointer: None,
+        old_value: Some(value.clone()),
+        new_value: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_not_report_reordered_entries() {
+        let old = Metadata::from_str(MTB_JSON).unwrap();
+        let mut new = Metadata::from_str(MTB_JSON).unwrap();
+        new.donors.reverse();
+        new.donors[1].lab_data.reverse();
+
+        assert!(diff(&old, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_report_changes_by_key() {
+        let old = Metadata::from_str(MTB_JSON).unwrap();
+        let mut new = Metadata::from_str(MTB_JSON).unwrap();
+        new.donors.reverse();
+        let lab_datum = &mut new.donors[1].lab_data[1];
+        lab_datum
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .mean_depth_of_coverage = 300.0;
+        lab_datum.sequence_data.as_mut().unwrap().files.remove(0);
+        new.donors[0].lab_data.clear();
+
+        let diff = diff(&old, &new).unwrap();
+        let donor = &old.donors[0].donor_pseudonym;
+
+        assert_eq!(
+            diff.changes
+                .iter()
+                .map(|change| (change.kind, change.path.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    ChangeKind::Removed,
+                    format!(
+                        "donors[{donor}]/labData[Blut DNA Tumor]/sequenceData/files[GRCh38_target_region_file.bed]"
+                    )
+                    .as_str()
+                ),
+                (
+                    ChangeKind::Changed,
+                    format!("donors[{donor}]/labData[Blut DNA Tumor]/sequenceData/meanDepthOfCoverage")
+                        .as_str()
+                ),
+                (
+                    ChangeKind::Removed,
+                    format!("donors[{}]/labData[Blut DNA normal]", old.donors[1].donor_pseudonym)
+                        .as_str()
+                ),
+            ]
+        );
+        assert_eq!(
+            diff.changes[1].new_pointer.as_deref(),
+            Some("/donors/1/labData/1/sequenceData/meanDepthOfCoverage")
+        );
+        assert!(diff.to_string().contains("~ "));
+    }
+}
@@ -0,0 +1,408 @@
+//! Inspection of FASTQ files to cross-check flowcell, lane, read order and read length given in
+//! their `File` entry.
+
+use crate::files::{invalid_data, open_decompressed};
+use crate::validation::{Diagnostic, sequence_data_with_pointer};
+use crate::{File, FileType, LabDatum, LibraryType, Metadata, ReadOrder};
+use std::io::{self, BufRead};
+use std::path::Path;
+
+/// Number of records read from each FASTQ file by `check_fastq_files`.
+pub const DEFAULT_RECORDS: usize = 10_000;
+
+/// Information contained in the header line of a FASTQ record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadHeader {
+    /// Instrument ID
+    pub instrument: String,
+
+    /// Flowcell ID, only available in Casava 1.8+ style headers
+    pub flowcell_id: Option<String>,
+
+    /// Lane number
+    pub lane: u32,
+
+    /// Member of a pair, 1 or 2 for paired-end reads
+    pub read_number: Option<u8>,
+}
+
+impl ReadHeader {
+    /// Parses an Illumina style FASTQ header line.
+    ///
+    /// Supports Casava 1.8+ headers like '@EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG'
+    /// and older headers like '@HWUSI-EAS100R:6:73:941:1973#0/1'.
+    pub fn parse(line: &str) -> Option<ReadHeader> {
+        let line = line.strip_prefix('@')?;
+        let (name, comment) = match line.split_once([' ', '\t']) {
+            Some((name, comment)) => (name, Some(comment.trim())),
+            None => (line, None),
+        };
+
+        let fields = name.split(':').collect::<Vec<_>>();
+        match fields.len() {
+            7 => Some(ReadHeader {
+                instrument: fields[0].to_string(),
+                flowcell_id: Some(fields[2].to_string()),
+                lane: fields[3].parse().ok()?,
+                read_number: comment
+                    .and_then(|comment| comment.split(':').next())
+                    .and_then(|read_number| read_number.parse().ok()),
+            }),
+            5 => Some(ReadHeader {
+                instrument: fields[0].to_string(),
+                flowcell_id: None,
+                lane: fields[1].parse().ok()?,
+                read_number: fields[4]
+                    .rsplit_once('/')
+                    .and_then(|(_, read_number)| read_number.parse().ok()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Summary of the first records of a FASTQ file.
+#[derive(Debug, Default, PartialEq)]
+pub struct FastqSummary {
+    /// Number of records read
+    pub records: usize,
+
+    /// Number of records with a header that could not be parsed
+    pub unparsed_headers: usize,
+
+    /// Distinct flowcell IDs in order of appearance
+    pub flowcell_ids: Vec<String>,
+
+    /// Distinct lane numbers in order of appearance
+    pub lanes: Vec<u32>,
+
+    /// Distinct read numbers in order of appearance
+    pub read_numbers: Vec<u8>,
+
+    /// Length of the longest read
+    pub max_read_length: usize,
+
+    /// Total number of bases of all reads
+    pub total_bases: usize,
+}
+
+impl FastqSummary {
+    /// Rounded average read length
+    pub fn mean_read_length(&self) -> usize {
+        if self.records == 0 {
+            return 0;
+        }
+        (self.total_bases as f64 / self.records as f64).round() as usize
+    }
+}
+
+/// Reads at most `max_records` records of FASTQ data and summarizes their headers and lengths.
+///
+/// # Errors
+///
+/// If the data cannot be read or is not valid FASTQ, an `io::Error` will be returned.
+pub fn inspect<R: BufRead>(reader: R, max_records: usize) -> io::Result<FastqSummary> {
+    let mut summary = FastqSummary::default();
+    let mut lines = reader.lines();
+
+    while summary.records < max_records {
+        let Some(header) = lines.next().transpose()? else {
+            break;
+        };
+        if header.is_empty() {
+            continue;
+        }
+        let sequence = lines.next().transpose()?;
+        let separator = lines.next().transpose()?;
+        let quality = lines.next().transpose()?;
+        let (Some(sequence), Some(separator), Some(_)) = (sequence, separator, quality) else {
+            return Err(invalid_data("incomplete FASTQ record"));
+        };
+        if !header.starts_with('@') || !separator.starts_with('+') {
+            return Err(invalid_data("invalid FASTQ record"));
+        }
+
+        summary.records += 1;
+        summary.total_bases += sequence.len();
+        summary.max_read_length = summary.max_read_length.max(sequence.len());
+
+        match ReadHeader::parse(&header) {
+            Some(header) => {
+                if let Some(flowcell_id) = header.flowcell_id
+                    && !summary.flowcell_ids.contains(&flowcell_id)
+                {
+                    summary.flowcell_ids.push(flowcell_id);
+                }
+                if !summary.lanes.contains(&header.lane) {
+                    summary.lanes.push(header.lane);
+                }
+                if let Some(read_number) = header.read_number
+                    && !summary.read_numbers.contains(&read_number)
+                {
+                    summary.read_numbers.push(read_number);
+                }
+            }
+            None => summary.unparsed_headers += 1,
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Reads at most `max_records` records of a plain or gzip compressed FASTQ file.
+///
+/// # Errors
+///
+/// If the file cannot be read or is not valid FASTQ, an `io::Error` will be returned.
+pub fn inspect_file(path: &Path, max_records: usize) -> io::Result<FastqSummary> {
+    inspect(open_decompressed(path)?, max_records)
+}
+
+/// Compares the FASTQ summary with flowcell, lane, read order and read length of the `File`
+/// entry located at `pointer`.
+pub fn compare(
+    file: &File,
+    lab_datum: &LabDatum,
+    summary: &FastqSummary,
+    pointer: &str,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    if summary.unparsed_headers > 0 {
+        diagnostics.push(Diagnostic::warning(
+            "fastq-unknown-header",
+            pointer,
+            format!(
+                "{} of {} read headers in '{}' are not Illumina style headers",
+                summary.unparsed_headers, summary.records, file.file_path
+            ),
+        ));
+    }
+
+    if let Some(flowcell_id) = &file.flowcell_id
+        && !summary.flowcell_ids.is_empty()
+        && summary.flowcell_ids != [flowcell_id.as_str()]
+    {
+        diagnostics.push(Diagnostic::error(
+            "fastq-flowcell-mismatch",
+            format!("{pointer}/flowcellId"),
+            format!(
+                "flowcell '{}' does not match flowcell {} in read headers",
+                flowcell_id,
+                quoted_list(&summary.flowcell_ids)
+            ),
+        ));
+    }
+
+    if let Some(lane_id) = &file.lane_id
+        && !summary.lanes.is_empty()
+    {
+        let lane = lane_id
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse::<u32>()
+            .ok();
+        if lane.is_none_or(|lane| summary.lanes != [lane]) {
+            diagnostics.push(Diagnostic::error(
+                "fastq-lane-mismatch",
+                format!("{pointer}/laneId"),
+                format!(
+                    "lane '{}' does not match lane {} in read headers",
+                    lane_id,
+                    quoted_list(&summary.lanes)
+                ),
+            ));
+        }
+    }
+
+    if let Some(read_order) = &file.read_order
+        && !summary.read_numbers.is_empty()
+    {
+        let read_number = match read_order {
+            ReadOrder::R1 => 1,
+            ReadOrder::R2 => 2,
+        };
+        if summary.read_numbers != [read_number] {
+            diagnostics.push(Diagnostic::error(
+                "fastq-read-order-mismatch",
+                format!("{pointer}/readOrder"),
+                format!(
+                    "read order {:?} does not match read number {} in read headers",
+                    read_order,
+                    quoted_list(&summary.read_numbers)
+                ),
+            ));
+        }
+    }
+
+    if let Some(read_length) = file.read_length
+        && summary.records > 0
+    {
+        let long_read = matches!(
+            lab_datum.library_type,
+            LibraryType::PanelLr | LibraryType::WesLr | LibraryType::WgsLr | LibraryType::WxsLr
+        );
+        let (actual, matches) = if long_read {
+            let mean = summary.mean_read_length();
+            (
+                mean,
+                (mean as f64 - read_length as f64).abs() <= 0.1 * mean as f64,
+            )
+        } else {
+            let max = summary.max_read_length;
+            (max, max as i64 == read_length)
+        };
+        if !matches {
+            diagnostics.push(Diagnostic::error(
+                "fastq-read-length-mismatch",
+                format!("{pointer}/readLength"),
+                format!(
+                    "read length {} does not match {} read length {} of the first {} reads",
+                    read_length,
+                    if long_read { "average" } else { "maximum" },
+                    actual,
+                    summary.records
+                ),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Inspects the first `DEFAULT_RECORDS` records of all FASTQ files referenced in the metadata
+/// and reports mismatches with their `File` entries.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::fastq::check_fastq_files;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     for diagnostic in check_fastq_files(&metadata, Path::new("submission/files")) {
+///         println!("{}", diagnostic);
+///     }
+/// }
+/// ```
+pub fn check_fastq_files(metadata: &Metadata, files_dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for (pointer, lab_datum, sequence_data) in sequence_data_with_pointer(metadata) {
+        for (f, file) in sequence_data.files.iter().enumerate() {
+            if file.file_type != FileType::Fastq {
+                continue;
+            }
+            let pointer = format!("{pointer}/files/{f}");
+            let Ok(path) = file.relative_file_path() else {
+                continue;
+            };
+            match inspect_file(&path.to_path(files_dir), DEFAULT_RECORDS) {
+                Ok(summary) => {
+                    diagnostics.extend(compare(file, lab_datum, &summary, &pointer));
+                }
+                Err(err) => diagnostics.push(Diagnostic::error(
+                    "fastq-unreadable",
+                    pointer,
+                    format!("'{}' cannot be read: {}", file.file_path, err),
+                )),
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn quoted_list<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| format!("'{}'", value.to_string()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    const FASTQ: &str = "@A00123:8:HKJ3VDSX3:2:1101:1000:1000 1:N:0:ATCACG
+ACGTACGTAC
++
+FFFFFFFFFF
+@A00123:8:HKJ3VDSX3:2:1101:1000:2000 1:N:0:ATCACG
+ACGTACGT
++
+FFFFFFFF
+";
+
+    #[test]
+    fn should_parse_read_headers() {
+        assert_eq!(
+            ReadHeader::parse("@EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG"),
+            Some(ReadHeader {
+                instrument: "EAS139".to_string(),
+                flowcell_id: Some("FC706VJ".to_string()),
+                lane: 2,
+                read_number: Some(1),
+            })
+        );
+        assert_eq!(
+            ReadHeader::parse("@HWUSI-EAS100R:6:73:941:1973#0/2"),
+            Some(ReadHeader {
+                instrument: "HWUSI-EAS100R".to_string(),
+                flowcell_id: None,
+                lane: 6,
+                read_number: Some(2),
+            })
+        );
+        assert_eq!(ReadHeader::parse("@SRR001666.1 071112_SLXA"), None);
+    }
+
+    #[test]
+    fn should_report_mismatches_of_gzipped_fastq_file() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let files_dir = tempfile::tempdir().unwrap();
+
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(files_dir.path().join("a_R1.fastq.gz")).unwrap(),
+            Compression::default(),
+        );
+        encoder.write_all(FASTQ.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let file = &mut metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files[1];
+        file.file_path = "a_R1.fastq.gz".to_string();
+        file.file_type = FileType::Fastq;
+        file.flowcell_id = Some("HKJ3VDSX3".to_string());
+        file.lane_id = Some("L001".to_string());
+        file.read_order = Some(ReadOrder::R2);
+
+        let diagnostics = check_fastq_files(&metadata, files_dir.path());
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.pointer.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "/donors/0/labData/0/sequenceData/files/1/laneId",
+                "/donors/0/labData/0/sequenceData/files/1/readOrder",
+                "/donors/0/labData/0/sequenceData/files/1/readLength",
+            ]
+        );
+    }
+}
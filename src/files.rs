@@ -0,0 +1,334 @@
+use crate::checksum::sha256;
+use crate::{ChecksumType, File, FileType};
+use flate2::read::MultiGzDecoder;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct FileError(String);
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata File Error: {}", self.0)
+    }
+}
+
+impl Error for FileError {}
+
+/// A file path relative to the submission files directory.
+///
+/// The path is guaranteed to be neither absolute nor to contain '..' segments, so it can be
+/// safely joined with a local directory. Backslashes are normalized to forward slashes, empty
+/// and '.' segments are removed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RelativeFilePath(String);
+
+impl RelativeFilePath {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the path of this file located in the given directory.
+    pub fn to_path(&self, files_dir: &Path) -> PathBuf {
+        self.0
+            .split('/')
+            .fold(files_dir.to_path_buf(), |path, segment| path.join(segment))
+    }
+}
+
+impl FromStr for RelativeFilePath {
+    type Err = FileError;
+
+    /// Parses and normalizes a file path relative to the submission files directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::RelativeFilePath;
+    /// use std::str::FromStr;
+    ///
+    /// fn main() {
+    ///     let path = RelativeFilePath::from_str(r"patient_001\.\dna.bam").unwrap();
+    ///     assert_eq!(path.as_str(), "patient_001/dna.bam");
+    ///
+    ///     assert!(RelativeFilePath::from_str("../dna.bam").is_err());
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the path is empty, absolute, contains '..' segments or control characters, an
+    /// `FileError` will be returned.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let path = value.replace('\\', "/");
+
+        if path.chars().any(char::is_control) {
+            return Err(FileError(format!("{value:?} contains control characters")));
+        }
+        if path.starts_with('/') || has_drive_prefix(&path) {
+            return Err(FileError(format!("{value:?} is an absolute path")));
+        }
+
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty() && *segment != ".")
+            .collect::<Vec<_>>();
+        if segments.contains(&"..") {
+            return Err(FileError(format!("{value:?} refers to a parent directory")));
+        }
+        if segments.is_empty() {
+            return Err(FileError(format!("{value:?} is not a file path")));
+        }
+
+        Ok(RelativeFilePath(segments.join("/")))
+    }
+}
+
+impl Display for RelativeFilePath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for RelativeFilePath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn has_drive_prefix(path: &str) -> bool {
+    let mut chars = path.chars();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some(drive), Some(':')) if drive.is_ascii_alphabetic()
+    )
+}
+
+impl FileType {
+    /// Infers the file type from the extension of the given file name.
+    ///
+    /// Recognizes '.bam', '.bed', '.vcf', '.vcf.gz', '.vcf.bgz', '.fastq', '.fastq.gz', '.fq' and
+    /// '.fq.gz'. Other compressed files, e.g. '.bam.gz' or '.bed.gz', are not recognized.
+    pub fn from_file_name(file_name: &str) -> Option<FileType> {
+        let file_name = file_name.to_ascii_lowercase();
+        let (file_name, compression) = match file_name.rsplit_once('.') {
+            Some((file_name, compression @ ("gz" | "bgz"))) => (file_name, Some(compression)),
+            _ => (file_name.as_str(), None),
+        };
+        let extension = file_name.rsplit_once('.').map(|(_, extension)| extension);
+        match (extension, compression) {
+            (Some("bam"), None) => Some(FileType::Bam),
+            (Some("bed"), None) => Some(FileType::Bed),
+            (Some("vcf"), None | Some("gz" | "bgz")) => Some(FileType::Vcf),
+            (Some("fastq" | "fq"), None | Some("gz")) => Some(FileType::Fastq),
+            _ => None,
+        }
+    }
+}
+
+impl File {
+    /// Returns the validated and normalized file path of this file.
+    ///
+    /// # Errors
+    ///
+    /// If the file path is not a safe relative path, an `FileError` will be returned.
+    pub fn relative_file_path(&self) -> Result<RelativeFilePath, FileError> {
+        RelativeFilePath::from_str(&self.file_path)
+    }
+
+    /// Creates a `File` entry for a file located in the submission files directory.
+    ///
+    /// The file path is made relative to `files_dir`, size and SHA-256 checksum are calculated
+    /// from the file content and the file type is inferred from the file extension. A symlink is
+    /// given by its own name, not by the name of the file it links to.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use mv64e_grz_dto::File;
+    /// use std::path::Path;
+    ///
+    /// fn main() {
+    ///     let file = File::from_path(
+    ///         Path::new("submission/files"),
+    ///         Path::new("submission/files/patient_001/patient_001_dna.bam"),
+    ///     )
+    ///     .unwrap();
+    ///     assert_eq!(file.file_path, "patient_001/patient_001_dna.bam");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the file is not located in `files_dir`, cannot be read or has an unknown file type,
+    /// an `FileError` will be returned.
+    pub fn from_path(files_dir: &Path, path: &Path) -> Result<File, FileError> {
+        let files_dir = files_dir
+            .canonicalize()
+            .map_err(|err| FileError(format!("{}: {}", files_dir.display(), err)))?;
+        // Only the parent directory is resolved, so a symlink keeps its own name
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| FileError(format!("{} is not a file path", path.display())))?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let full_path = parent
+            .canonicalize()
+            .map_err(|err| FileError(format!("{}: {}", path.display(), err)))?
+            .join(file_name);
+
+        let relative_path = full_path.strip_prefix(&files_dir).map_err(|_| {
+            FileError(format!(
+                "{} is not located in {}",
+                path.display(),
+                files_dir.display()
+            ))
+        })?;
+        let file_path = relative_path
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => name.to_str().ok_or_else(|| {
+                    FileError(format!("{} is not a valid UTF-8 path", path.display()))
+                }),
+                _ => Err(FileError(format!("{} is not a valid path", path.display()))),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .join("/");
+
+        let file_type = FileType::from_file_name(&file_path)
+            .ok_or_else(|| FileError(format!("{file_path} has an unknown file type")))?;
+
+        let map_io_err = |err: std::io::Error| FileError(format!("{}: {}", path.display(), err));
+        let file_size = fs::metadata(&full_path).map_err(map_io_err)?.len();
+        let file_checksum =
+            sha256(fs::File::open(&full_path).map_err(map_io_err)?, |_| {}).map_err(map_io_err)?;
+
+        Ok(File {
+            checksum_type: Some(ChecksumType::Sha256),
+            file_checksum,
+            file_path,
+            file_size_in_bytes: file_size as f64,
+            file_type,
+            flowcell_id: None,
+            lane_id: None,
+            read_length: None,
+            read_order: None,
+        })
+    }
+}
+
+/// Opens a file for reading and transparently decompresses gzip and BGZF compressed content.
+pub(crate) fn open_decompressed(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Creates an error for malformed content of a sequencing or metrics file.
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_normalize_relative_file_path() {
+        let path = RelativeFilePath::from_str(r"patient_001\.//dna.bam").unwrap();
+        assert_eq!(path.as_str(), "patient_001/dna.bam");
+        assert_eq!(
+            path.to_path(Path::new("files")),
+            Path::new("files").join("patient_001").join("dna.bam")
+        );
+    }
+
+    #[test]
+    fn should_reject_unsafe_relative_file_path() {
+        for path in [
+            "",
+            "./",
+            "/etc/passwd",
+            r"\\server\share\dna.bam",
+            "C:/dna.bam",
+            "c:dna.bam",
+            "patient_001/../../dna.bam",
+            r"..\dna.bam",
+            "dna\0.bam",
+        ] {
+            assert!(RelativeFilePath::from_str(path).is_err(), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn should_infer_file_type() {
+        assert_eq!(FileType::from_file_name("a.bam"), Some(FileType::Bam));
+        assert_eq!(FileType::from_file_name("a.bed"), Some(FileType::Bed));
+        assert_eq!(FileType::from_file_name("a.vcf.gz"), Some(FileType::Vcf));
+        assert_eq!(
+            FileType::from_file_name("a_R1.FASTQ.GZ"),
+            Some(FileType::Fastq)
+        );
+        assert_eq!(FileType::from_file_name("a.vcf.bgz"), Some(FileType::Vcf));
+        assert_eq!(FileType::from_file_name("a.txt"), None);
+        assert_eq!(FileType::from_file_name("gz"), None);
+        for file_name in ["a.bam.gz", "a.bed.gz", "a.fastq.bgz", "a.bam.bgz"] {
+            assert_eq!(FileType::from_file_name(file_name), None, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn should_create_file_from_path() {
+        let files_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(files_dir.path().join("patient_001")).unwrap();
+        let path = files_dir.path().join("patient_001").join("dna.vcf.gz");
+        fs::write(&path, "abc").unwrap();
+
+        let file = File::from_path(files_dir.path(), &path).unwrap();
+
+        assert_eq!(file.file_path, "patient_001/dna.vcf.gz");
+        assert_eq!(file.file_type, FileType::Vcf);
+        assert_eq!(file.file_size_in_bytes, 3.0);
+        assert_eq!(file.checksum_type, Some(ChecksumType::Sha256));
+        assert_eq!(
+            file.file_checksum,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_keep_name_of_symlink() {
+        let files_dir = tempfile::tempdir().unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        let target = other_dir.path().join("upload_1234.tmp");
+        fs::write(&target, "abc").unwrap();
+        let path = files_dir.path().join("dna.bam");
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+
+        let file = File::from_path(files_dir.path(), &path).unwrap();
+
+        assert_eq!(file.file_path, "dna.bam");
+        assert_eq!(file.file_type, FileType::Bam);
+        assert_eq!(file.file_size_in_bytes, 3.0);
+    }
+
+    #[test]
+    fn should_reject_file_outside_files_dir() {
+        let files_dir = tempfile::tempdir().unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        let path = other_dir.path().join("dna.bam");
+        fs::write(&path, "abc").unwrap();
+
+        assert!(File::from_path(files_dir.path(), &path).is_err());
+    }
+}
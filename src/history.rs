@@ -0,0 +1,541 @@
+//! Lineage of the submissions of a single case, identified by `Submission.local_case_id`.
+
+use crate::validation::Diagnostic;
+use crate::{Donor, LabDatum, Metadata, Submission, SubmissionType, value_name};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, PartialEq)]
+pub struct HistoryError(String);
+
+impl Display for HistoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata History Error: {}", self.0)
+    }
+}
+
+impl Error for HistoryError {}
+
+/// All submissions of a case in chronological order.
+///
+/// Test submissions are kept, but are no part of the lineage and do not change the effective
+/// state of the case.
+#[derive(Debug)]
+pub struct CaseHistory {
+    local_case_id: String,
+
+    submissions: Vec<Metadata>,
+}
+
+impl CaseHistory {
+    /// Creates the history of a case from its submissions in any order.
+    ///
+    /// Submissions are ordered by submission date, an initial submission precedes other
+    /// submissions of the same date. Otherwise, the given order is kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::Metadata;
+    /// use mv64e_grz_dto::history::CaseHistory;
+    /// use std::str::FromStr;
+    ///
+    /// fn main() {
+    ///     const JSON: &str = include_str!("../tests/example_metadata.json");
+    ///
+    ///     let history = CaseHistory::new(vec![Metadata::from_str(JSON).unwrap()]).unwrap();
+    ///     for donor in history.effective_state().donors {
+    ///         println!("{}: {} lab data", donor.donor.donor_pseudonym, donor.lab_data.len());
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the submissions belong to different cases or the sequence of submission types is not
+    /// legal, a `HistoryError` will be returned.
+    pub fn new(mut submissions: Vec<Metadata>) -> Result<CaseHistory, HistoryError> {
+        let Some(first) = submissions.first() else {
+            return Err(HistoryError("no submissions given".to_string()));
+        };
+        let local_case_id = first.submission.local_case_id.clone();
+
+        submissions.sort_by(|a, b| {
+            (
+                &a.submission.submission_date,
+                a.submission.submission_type != SubmissionType::Initial,
+            )
+                .cmp(&(
+                    &b.submission.submission_date,
+                    b.submission.submission_type != SubmissionType::Initial,
+                ))
+        });
+
+        let mut history = CaseHistory {
+            local_case_id,
+            submissions: Vec::with_capacity(submissions.len()),
+        };
+        for metadata in submissions {
+            history.push(metadata)?;
+        }
+        Ok(history)
+    }
+
+    /// Appends the next submission of the case.
+    ///
+    /// # Errors
+    ///
+    /// If the submission belongs to another case, is dated before the latest submission or its
+    /// type is not allowed at this point, a `HistoryError` will be returned.
+    pub fn push(&mut self, metadata: Metadata) -> Result<(), HistoryError> {
+        let submission = &metadata.submission;
+        if submission.local_case_id != self.local_case_id {
+            return Err(HistoryError(format!(
+                "submission of case '{}' does not belong to case '{}'",
+                submission.local_case_id, self.local_case_id
+            )));
+        }
+        if let Some(latest) = self.submissions.last()
+            && submission.submission_date < latest.submission.submission_date
+        {
+            return Err(HistoryError(format!(
+                "submission of {} is dated before the latest submission of {}",
+                submission.submission_date, latest.submission.submission_date
+            )));
+        }
+
+        let has_initial = self
+            .submissions
+            .iter()
+            .any(|metadata| metadata.submission.submission_type == SubmissionType::Initial);
+        match submission.submission_type {
+            SubmissionType::Initial if has_initial => {
+                return Err(HistoryError(format!(
+                    "case '{}' already has an initial submission",
+                    self.local_case_id
+                )));
+            }
+            SubmissionType::Addition | SubmissionType::Correction | SubmissionType::Followup
+                if !has_initial =>
+            {
+                return Err(HistoryError(format!(
+                    "{} of {} precedes the initial submission of case '{}'",
+                    type_name(&submission.submission_type),
+                    submission.submission_date,
+                    self.local_case_id
+                )));
+            }
+            _ => {}
+        }
+
+        self.submissions.push(metadata);
+        Ok(())
+    }
+
+    pub fn local_case_id(&self) -> &str {
+        &self.local_case_id
+    }
+
+    /// Returns all submissions in chronological order.
+    pub fn submissions(&self) -> &[Metadata] {
+        &self.submissions
+    }
+
+    /// Checks the consistency of all submissions of the case, see `check_consistency`.
+    pub fn check_consistency(&self) -> Vec<CaseDiagnostic> {
+        check_consistency(&self.submissions)
+    }
+
+    /// Computes the current state of the case from all submissions except tests.
+    ///
+    /// Each submission adds donors and lab data or replaces those with the same donor pseudonym
+    /// and lab data name. This also applies to corrections, so lab data not repeated in a
+    /// correction are kept as they were submitted before.
+    pub fn effective_state(&self) -> EffectiveState<'_> {
+        let mut donors: Vec<(&Donor, Vec<&LabDatum>)> = vec![];
+        let mut latest = None;
+
+        for metadata in &self.submissions {
+            if metadata.submission.submission_type == SubmissionType::Test {
+                continue;
+            }
+            latest = Some(&metadata.submission);
+
+            for donor in &metadata.donors {
+                let position = donors
+                    .iter()
+                    .position(|(known, _)| known.donor_pseudonym == donor.donor_pseudonym);
+                let lab_data = match position {
+                    Some(position) => {
+                        donors[position].0 = donor;
+                        &mut donors[position].1
+                    }
+                    None => {
+                        donors.push((donor, vec![]));
+                        &mut donors.last_mut().expect("donor was just added").1
+                    }
+                };
+                for lab_datum in &donor.lab_data {
+                    match lab_data
+                        .iter_mut()
+                        .find(|known| known.lab_data_name == lab_datum.lab_data_name)
+                    {
+                        Some(entry) => *entry = lab_datum,
+                        None => lab_data.push(lab_datum),
+                    }
+                }
+            }
+        }
+
+        EffectiveState {
+            submission: latest,
+            donors: donors
+                .into_iter()
+                .map(|(donor, lab_data)| EffectiveDonor { donor, lab_data })
+                .collect(),
+        }
+    }
+}
+
+/// Current state of a case.
+#[derive(Debug)]
+pub struct EffectiveState<'a> {
+    /// Latest submission except tests, `None` if there are only test submissions
+    pub submission: Option<&'a Submission>,
+
+    pub donors: Vec<EffectiveDonor<'a>>,
+}
+
+/// Latest version of a donor along with the latest version of all its lab data.
+#[derive(Debug)]
+pub struct EffectiveDonor<'a> {
+    /// Donor as given in the latest submission containing it, use `lab_data` instead of the
+    /// lab data of this donor
+    pub donor: &'a Donor,
+
+    pub lab_data: Vec<&'a LabDatum>,
+}
+
+/// A diagnostic of a single submission within a case.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaseDiagnostic {
+    /// Index of the affected submission in the given list of submissions
+    pub submission: usize,
+
+    /// Diagnostic with a JSON pointer into the affected submission
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+}
+
+/// Checks that several submissions of one case are consistent with each other.
+///
+/// The following rules are checked, each finding refers to the later submission:
+/// - all submissions have the same `local_case_id`
+/// - each submission has its own `tan_g`
+/// - `submitter_id` and `genomic_data_center_id` do not change
+/// - donors with the same pseudonym keep their gender and relation
+///
+/// Test submissions are only checked for a unique `tan_g`.
+///
+/// # Example
+///
+/// ```
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::history::check_consistency;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let submissions = vec![
+///         Metadata::from_str(JSON).unwrap(),
+///         Metadata::from_str(JSON).unwrap(),
+///     ];
+///     for finding in check_consistency(&submissions) {
+///         println!("submission {}: {}", finding.submission, finding.diagnostic);
+///     }
+/// }
+/// ```
+pub fn check_consistency(submissions: &[Metadata]) -> Vec<CaseDiagnostic> {
+    let mut diagnostics = vec![];
+    let mut push = |submission, diagnostic| {
+        diagnostics.push(CaseDiagnostic {
+            submission,
+            diagnostic,
+        });
+    };
+
+    let mut tans: HashMap<&str, usize> = HashMap::new();
+    for (i, metadata) in submissions.iter().enumerate() {
+        let tan_g = metadata.submission.tan_g.as_str();
+        match tans.get(tan_g) {
+            Some(first) => push(
+                i,
+                Diagnostic::error(
+                    "duplicate-tan-g",
+                    "/submission/tanG",
+                    format!("tanG is already used by submission {first}"),
+                ),
+            ),
+            None => {
+                tans.insert(tan_g, i);
+            }
+        }
+    }
+
+    let mut lineage = submissions
+        .iter()
+        .enumerate()
+        .filter(|(_, metadata)| metadata.submission.submission_type != SubmissionType::Test);
+    let Some((first_index, first)) = lineage.next() else {
+        return diagnostics;
+    };
+    let mut donors: HashMap<&str, (usize, &Donor)> = first
+        .donors
+        .iter()
+        .map(|donor| (donor.donor_pseudonym.as_str(), (first_index, donor)))
+        .collect();
+
+    for (i, metadata) in lineage {
+        let submission = &metadata.submission;
+        let stable_fields = [
+            (
+                "localCaseId",
+                &first.submission.local_case_id,
+                &submission.local_case_id,
+            ),
+            (
+                "submitterId",
+                &first.submission.submitter_id,
+                &submission.submitter_id,
+            ),
+            (
+                "genomicDataCenterId",
+                &first.submission.genomic_data_center_id,
+                &submission.genomic_data_center_id,
+            ),
+        ];
+        for (field, expected, actual) in stable_fields {
+            if expected != actual {
+                push(
+                    i,
+                    Diagnostic::error(
+                        "inconsistent-submission",
+                        format!("/submission/{field}"),
+                        format!(
+                            "{field} '{actual}' differs from '{expected}' in submission {first_index}"
+                        ),
+                    ),
+                );
+            }
+        }
+
+        for (j, donor) in metadata.donors.iter().enumerate() {
+            let Some((known_index, known)) = donors.get(donor.donor_pseudonym.as_str()) else {
+                donors.insert(&donor.donor_pseudonym, (i, donor));
+                continue;
+            };
+            if known.gender != donor.gender {
+                push(
+                    i,
+                    Diagnostic::error(
+                        "inconsistent-donor",
+                        format!("/donors/{j}/gender"),
+                        format!(
+                            "gender '{}' of donor '{}' differs from '{}' in submission {known_index}",
+                            value_name(&donor.gender),
+                            donor.donor_pseudonym,
+                            value_name(&known.gender)
+                        ),
+                    ),
+                );
+            }
+            if known.relation != donor.relation {
+                push(
+                    i,
+                    Diagnostic::error(
+                        "inconsistent-donor",
+                        format!("/donors/{j}/relation"),
+                        format!(
+                            "relation '{}' of donor '{}' differs from '{}' in submission {known_index}",
+                            value_name(&donor.relation),
+                            donor.donor_pseudonym,
+                            value_name(&known.relation)
+                        ),
+                    ),
+                );
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn type_name(submission_type: &SubmissionType) -> &'static str {
+    match submission_type {
+        SubmissionType::Addition => "addition",
+        SubmissionType::Correction => "correction",
+        SubmissionType::Followup => "followup",
+        SubmissionType::Initial => "initial submission",
+        SubmissionType::Test => "test submission",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    fn submission(submission_type: SubmissionType, date: &str) -> Metadata {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        metadata.submission.submission_type = submission_type;
+        metadata.submission.submission_date = date.to_string();
+        metadata
+    }
+
+    #[test]
+    fn should_order_submissions_and_reject_illegal_transitions() {
+        let history = CaseHistory::new(vec![
+            submission(SubmissionType::Followup, "2024-09-01"),
+            submission(SubmissionType::Correction, "2024-07-15"),
+            submission(SubmissionType::Initial, "2024-07-15"),
+        ])
+        .unwrap();
+        assert_eq!(
+            history
+                .submissions()
+                .iter()
+                .map(|metadata| type_name(&metadata.submission.submission_type))
+                .collect::<Vec<_>>(),
+            vec!["initial submission", "correction", "followup"]
+        );
+
+        assert!(
+            CaseHistory::new(vec![submission(SubmissionType::Correction, "2024-07-15")]).is_err()
+        );
+        assert!(
+            CaseHistory::new(vec![
+                submission(SubmissionType::Initial, "2024-07-15"),
+                submission(SubmissionType::Initial, "2024-08-01"),
+            ])
+            .is_err()
+        );
+
+        let mut other_case = submission(SubmissionType::Followup, "2024-09-01");
+        other_case.submission.local_case_id = "other".to_string();
+        let mut history = history;
+        assert!(history.push(other_case).is_err());
+        assert!(
+            history
+                .push(submission(SubmissionType::Addition, "2024-08-01"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn should_compute_effective_state() {
+        let initial = submission(SubmissionType::Initial, "2024-07-15");
+
+        let mut followup = submission(SubmissionType::Followup, "2024-09-01");
+        followup.donors.truncate(1);
+        followup.donors[0].lab_data.truncate(1);
+        followup.donors[0].lab_data[0].lab_data_name = "Blut DNA Tumor 2".to_string();
+
+        let mut correction = submission(SubmissionType::Correction, "2024-09-02");
+        correction.donors.truncate(1);
+        correction.donors[0].lab_data.truncate(1);
+        correction.donors[0].lab_data[0].lab_data_name = "Blut DNA Tumor 3".to_string();
+
+        let history = CaseHistory::new(vec![initial, followup, correction]).unwrap();
+        let state = history.effective_state();
+
+        assert_eq!(
+            state
+                .submission
+                .map(|submission| submission.submission_date.as_str()),
+            Some("2024-09-02")
+        );
+        assert_eq!(state.donors.len(), 2);
+        assert_eq!(
+            state.donors[0]
+                .lab_data
+                .iter()
+                .map(|lab_datum| lab_datum.lab_data_name.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Blut DNA normal",
+                "Blut DNA Tumor",
+                "Blut DNA Tumor 2",
+                "Blut DNA Tumor 3"
+            ]
+        );
+        assert_eq!(state.donors[1].lab_data.len(), 1);
+    }
+
+    #[test]
+    fn should_keep_lab_data_not_repeated_in_correction() {
+        let initial = submission(SubmissionType::Initial, "2024-07-15");
+
+        let mut correction = submission(SubmissionType::Correction, "2024-07-16");
+        correction.donors.truncate(1);
+        correction.donors[0].lab_data.truncate(1);
+        correction.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .mean_depth_of_coverage = 42.0;
+
+        let history = CaseHistory::new(vec![initial, correction]).unwrap();
+        let state = history.effective_state();
+
+        assert_eq!(state.donors.len(), 2);
+        let lab_data = &state.donors[0].lab_data;
+        assert_eq!(lab_data.len(), 2);
+        assert_eq!(lab_data[0].lab_data_name, "Blut DNA normal");
+        assert_eq!(
+            lab_data[0]
+                .sequence_data
+                .as_ref()
+                .map(|sequence_data| sequence_data.mean_depth_of_coverage),
+            Some(42.0)
+        );
+        assert_eq!(lab_data[1].lab_data_name, "Blut DNA Tumor");
+        assert_eq!(state.donors[1].lab_data.len(), 1);
+    }
+
+    #[test]
+    fn should_report_inconsistent_submissions() {
+        let initial = submission(SubmissionType::Initial, "2024-07-15");
+
+        let mut followup = submission(SubmissionType::Followup, "2024-09-01");
+        followup.submission.submitter_id = "987654321".to_string();
+        followup.donors[1].gender = crate::Gender::Other;
+
+        let mut test = submission(SubmissionType::Test, "2024-09-02");
+        test.submission.submitter_id = "987654321".to_string();
+        test.submission.tan_g = "c".repeat(64);
+
+        let diagnostics = check_consistency(&[initial, followup, test]);
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|finding| (
+                    finding.submission,
+                    finding.diagnostic.code,
+                    finding.diagnostic.pointer.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "duplicate-tan-g", "/submission/tanG"),
+                (1, "inconsistent-submission", "/submission/submitterId"),
+                (1, "inconsistent-donor", "/donors/1/gender"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Integrity checks of compressed submission files to detect truncated or corrupt uploads.
+
+use crate::checksum::Progress;
+use crate::{File, FileType, Metadata};
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The empty BGZF block terminating every BGZF compressed file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// The EOF container terminating every CRAM 3.x file.
+const CRAM3_EOF: [u8; 38] = [
+    0x0f, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x0f, 0xe0, 0x45, 0x4f, 0x46, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x05, 0xbd, 0xd9, 0x4f, 0x00, 0x01, 0x00, 0x06, 0x06, 0x01, 0x00, 0x01, 0x00,
+    0x01, 0x00, 0xee, 0x63, 0x01, 0x4b,
+];
+
+/// Outcome of the integrity check of a single file.
+#[derive(Debug, PartialEq)]
+pub enum IntegrityStatus {
+    /// The file is complete and not corrupt
+    Ok,
+
+    /// The file is not compressed, so there is nothing to check
+    NotApplicable,
+
+    /// The file does not exist in the submission files directory
+    Missing,
+
+    /// The file is truncated or corrupt
+    Corrupt(String),
+
+    /// The file exists but could not be read
+    Unreadable(String),
+}
+
+impl IntegrityStatus {
+    /// Returns `true` unless the file is missing, truncated, corrupt or unreadable.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, IntegrityStatus::Ok | IntegrityStatus::NotApplicable)
+    }
+}
+
+/// Integrity check result of a single `File` entry.
+#[derive(Debug)]
+pub struct IntegrityResult {
+    /// Path of the file as given in the metadata
+    pub file_path: String,
+
+    pub status: IntegrityStatus,
+}
+
+/// Checks the integrity of all files referenced in the metadata.
+///
+/// BAM and compressed VCF files must end with a BGZF EOF marker, CRAM files with a CRAM EOF
+/// container. Compressed FASTQ files are decompressed completely to verify the gzip checksums of
+/// all members. VCF and FASTQ files with a '.gz' or '.bgz' extension but without gzip header are
+/// reported as corrupt, as well as VCF files compressed with plain gzip instead of BGZF.
+///
+/// Files are checked in parallel, the `progress` callback is only invoked while FASTQ files are
+/// being decompressed.
+///
+/// The results are returned in the order the files appear in the metadata, which is the same as
+/// for `checksum::verify_files`, so both can be reported side by side.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::integrity::check_integrity;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     for result in check_integrity(&metadata, Path::new("submission/files"), |_| {}) {
+///         if !result.status.is_ok() {
+///             println!("{}: {:?}", result.file_path, result.status);
+///         }
+///     }
+/// }
+/// ```
+pub fn check_integrity<P>(
+    metadata: &Metadata,
+    files_dir: &Path,
+    progress: P,
+) -> Vec<IntegrityResult>
+where
+    P: Fn(&Progress) + Sync,
+{
+    metadata
+        .files()
+        .collect::<Vec<_>>()
+        .par_iter()
+        .map(|file| IntegrityResult {
+            file_path: file.file_path.clone(),
+            status: check_file(files_dir, file, &progress),
+        })
+        .collect()
+}
+
+/// Checks the integrity of a single file based on its file type.
+pub fn check_file<P>(files_dir: &Path, file: &File, progress: &P) -> IntegrityStatus
+where
+    P: Fn(&Progress) + Sync,
+{
+    let path = match file.relative_file_path() {
+        Ok(path) => path.to_path(files_dir),
+        Err(err) => return IntegrityStatus::Unreadable(err.to_string()),
+    };
+
+    // Compressed files are determined by the extension, so a truncated file without a complete
+    // gzip header is not taken for an uncompressed file
+    let file_name = file.file_path.to_ascii_lowercase();
+    let compressed = file_name.ends_with(".gz") || file_name.ends_with(".bgz");
+
+    let result = fs::File::open(&path).and_then(|mut reader| {
+        let mut header = vec![];
+        (&mut reader).take(16).read_to_end(&mut header)?;
+        reader.seek(SeekFrom::Start(0))?;
+        let gzip = header.starts_with(&[0x1f, 0x8b]);
+
+        match file.file_type {
+            FileType::Bam if header.starts_with(b"CRAM") => check_cram_eof(reader),
+            FileType::Bam => check_bgzf_eof(reader),
+            FileType::Vcf | FileType::Fastq if compressed && !gzip => Ok(IntegrityStatus::Corrupt(
+                "missing gzip header, the file is truncated or not compressed".to_string(),
+            )),
+            FileType::Vcf if gzip && !is_bgzf(&header) => Ok(IntegrityStatus::Corrupt(
+                "not BGZF-compressed, but compressed with plain gzip".to_string(),
+            )),
+            FileType::Vcf if gzip => check_bgzf_eof(reader),
+            FileType::Fastq if gzip => check_gzip_stream(reader, &file.file_path, progress),
+            _ => Ok(IntegrityStatus::NotApplicable),
+        }
+    });
+
+    match result {
+        Ok(status) => status,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => IntegrityStatus::Missing,
+        Err(err) => IntegrityStatus::Unreadable(err.to_string()),
+    }
+}
+
+/// Returns `true` if the gzip header has the extra subfield 'BC' identifying a BGZF block.
+fn is_bgzf(header: &[u8]) -> bool {
+    header.len() >= 14 && header[3] & 0x04 != 0 && header[12..14] == *b"BC"
+}
+
+fn check_bgzf_eof(file: fs::File) -> io::Result<IntegrityStatus> {
+    if ends_with(file, &BGZF_EOF)? {
+        Ok(IntegrityStatus::Ok)
+    } else {
+        Ok(IntegrityStatus::Corrupt(
+            "missing BGZF EOF marker, the file is truncated".to_string(),
+        ))
+    }
+}
+
+fn check_cram_eof(mut file: fs::File) -> io::Result<IntegrityStatus> {
+    let mut definition = [0; 6];
+    file.read_exact(&mut definition)?;
+    if definition[4] != 3 {
+        return Ok(IntegrityStatus::NotApplicable);
+    }
+    if ends_with(file, &CRAM3_EOF)? {
+        Ok(IntegrityStatus::Ok)
+    } else {
+        Ok(IntegrityStatus::Corrupt(
+            "missing CRAM EOF container, the file is truncated".to_string(),
+        ))
+    }
+}
+
+fn check_gzip_stream<P>(
+    file: fs::File,
+    file_path: &str,
+    progress: &P,
+) -> io::Result<IntegrityStatus>
+where
+    P: Fn(&Progress) + Sync,
+{
+    let total_bytes = file.metadata()?.len();
+    let reader = ProgressReader {
+        inner: file,
+        bytes_processed: 0,
+        on_read: |bytes_processed| {
+            progress(&Progress {
+                file_path,
+                bytes_processed,
+                total_bytes,
+            })
+        },
+    };
+
+    match io::copy(&mut MultiGzDecoder::new(reader), &mut io::sink()) {
+        Ok(_) => Ok(IntegrityStatus::Ok),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::InvalidData
+                    | io::ErrorKind::InvalidInput
+                    | io::ErrorKind::UnexpectedEof
+            ) =>
+        {
+            Ok(IntegrityStatus::Corrupt(format!(
+                "invalid gzip stream: {err}"
+            )))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn ends_with(mut file: fs::File, suffix: &[u8]) -> io::Result<bool> {
+    let length = file.metadata()?.len();
+    if length < suffix.len() as u64 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-(suffix.len() as i64)))?;
+    let mut buffer = vec![0; suffix.len()];
+    file.read_exact(&mut buffer)?;
+    Ok(buffer == suffix)
+}
+
+struct ProgressReader<R, F> {
+    inner: R,
+    bytes_processed: u64,
+    on_read: F,
+}
+
+impl<R: Read, F: Fn(u64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_processed += read as u64;
+        (self.on_read)(self.bytes_processed);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn should_detect_truncated_files() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let files_dir = tempfile::tempdir().unwrap();
+
+        let files = &mut metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files;
+        files[0].file_path = "a.bed".to_string();
+        files[1].file_path = "a.bam".to_string();
+        let mut fastq = serde_json::to_value(&files[1]).unwrap();
+        fastq["filePath"] = "a_R1.fastq.gz".into();
+        fastq["fileType"] = "fastq".into();
+        files.push(serde_json::from_value(fastq).unwrap());
+        let mut vcf = serde_json::to_value(&files[1]).unwrap();
+        vcf["filePath"] = "a.vcf.gz".into();
+        vcf["fileType"] = "vcf".into();
+        files.push(serde_json::from_value(vcf).unwrap());
+
+        let mut bam = gzip(b"BAM\x01");
+        bam.extend(BGZF_EOF);
+        let fastq = gzip(b"@read\nACGT\n+\nFFFF\n");
+        fs::write(files_dir.path().join("a.bed"), "chr1\t0\t100\n").unwrap();
+        fs::write(files_dir.path().join("a.bam"), &bam).unwrap();
+        fs::write(
+            files_dir.path().join("a_R1.fastq.gz"),
+            &fastq[..fastq.len() - 4],
+        )
+        .unwrap();
+        fs::write(files_dir.path().join("a.vcf.gz"), gzip(b"##fileformat")).unwrap();
+
+        let results = check_integrity(&metadata, files_dir.path(), |_| {});
+
+        assert_eq!(results[0].status, IntegrityStatus::NotApplicable);
+        assert_eq!(results[1].status, IntegrityStatus::Ok);
+        assert!(matches!(results[2].status, IntegrityStatus::Corrupt(_)));
+        assert_eq!(
+            results[3].status,
+            IntegrityStatus::Corrupt("not BGZF-compressed, but compressed with plain gzip".into())
+        );
+        assert_eq!(results[4].status, IntegrityStatus::Missing);
+    }
+
+    #[test]
+    fn should_detect_compressed_files_without_gzip_header() {
+        let files_dir = tempfile::tempdir().unwrap();
+        let file = |file_path: &str, file_type: &str| -> File {
+            serde_json::from_value(serde_json::json!({
+                "filePath": file_path,
+                "fileType": file_type,
+                "fileChecksum": "0358a9852adc88c77e7321ddfb07caf2e9911986a90ff76816be142f6f38122d",
+                "fileSizeInBytes": 1
+            }))
+            .unwrap()
+        };
+
+        fs::write(files_dir.path().join("a_R1.fastq.gz"), b"").unwrap();
+        fs::write(files_dir.path().join("a.vcf.gz"), b"\x1f").unwrap();
+        fs::write(files_dir.path().join("a.vcf"), b"##fileformat").unwrap();
+        fs::write(files_dir.path().join("b.vcf.bgz"), BGZF_EOF).unwrap();
+
+        let status = |file_path, file_type| {
+            check_file(files_dir.path(), &file(file_path, file_type), &|_| {})
+        };
+
+        assert!(matches!(
+            status("a_R1.fastq.gz", "fastq"),
+            IntegrityStatus::Corrupt(_)
+        ));
+        assert!(matches!(
+            status("a.vcf.gz", "vcf"),
+            IntegrityStatus::Corrupt(_)
+        ));
+        assert_eq!(status("a.vcf", "vcf"), IntegrityStatus::NotApplicable);
+        assert_eq!(status("b.vcf.bgz", "vcf"), IntegrityStatus::Ok);
+    }
+}
@@ -3,12 +3,36 @@
 
 #![allow(clippy::needless_doctest_main)]
 
+use serde::Serialize;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::str::FromStr;
+pub use crate::files::*;
 pub use crate::metadata::*;
 
+pub mod alignment;
+pub mod bed;
+pub mod canonical;
+pub mod checksum;
+pub mod coverage;
+pub mod diff;
+pub mod fastq;
+pub mod history;
+pub mod integrity;
+pub mod metrics;
+pub mod numeric;
+pub mod patch;
+pub mod pedigree;
+pub mod qc;
+pub mod report;
+pub mod samplesheet;
+pub mod validation;
+pub mod vcf;
+mod files;
 mod metadata;
+mod reference;
+#[cfg(test)]
+mod test_fixtures;
 
 #[derive(Debug)]
 pub struct SerdeError(String);
@@ -48,6 +72,103 @@ impl FromStr for Metadata {
     }
 }
 
+impl Metadata {
+    /// Returns all files referenced by the lab data of all donors.
+    pub fn files(&self) -> impl Iterator<Item = &File> {
+        self.donors
+            .iter()
+            .flat_map(|donor| &donor.lab_data)
+            .filter_map(|lab_datum| lab_datum.sequence_data.as_ref())
+            .flat_map(|sequence_data| &sequence_data.files)
+    }
+
+    /// Returns the hex encoded SHA-256 fingerprint of the content, independent of the field
+    /// order and formatting of the JSON document the metadata has been read from.
+    ///
+    /// The fingerprint is the SHA-256 checksum of the RFC 8785 canonical form, see
+    /// `canonical::to_canonical_json`. Any other JCS implementation, e.g. Python's
+    /// `json.dumps(value, sort_keys=True, separators=(",", ":"), ensure_ascii=False)` for the
+    /// ASCII field names of the metadata, results in the same fingerprint.
+    ///
+    /// # Errors
+    ///
+    /// If the metadata cannot be serialized, an `SerdeError` will be returned.
+    pub fn fingerprint(&self) -> Result<String, SerdeError> {
+        let canonical = canonical::to_canonical_json(self)?;
+        Ok(checksum::sha256(canonical.as_bytes(), |_| {}).expect("reading from memory cannot fail"))
+    }
+
+    /// Returns the identifier of the submission in the format
+    /// `{submitter_id}_{submission_date}_{hash}` used for upload folders of the GRZ upload
+    /// tooling.
+    ///
+    /// The hash consists of the first eight characters of the hex encoded SHA-256 checksum of
+    /// the metadata JSON with sorted keys and without whitespace, which is the fingerprint. In
+    /// Python this is `sha256(json.dumps(metadata, sort_keys=True, separators=(",", ":"),
+    /// ensure_ascii=False).encode()).hexdigest()[:8]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::Metadata;
+    /// use std::str::FromStr;
+    ///
+    /// fn main() {
+    ///     const JSON: &str = include_str!("../tests/example_metadata.json");
+    ///
+    ///     let metadata = Metadata::from_str(JSON).unwrap();
+    ///     assert_eq!(metadata.submission_id().unwrap(), "260914050_2024-07-15_a4c58a77");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the metadata cannot be serialized, an `SerdeError` will be returned.
+    pub fn submission_id(&self) -> Result<String, SerdeError> {
+        Ok(format!(
+            "{}_{}_{}",
+            self.submission.submitter_id,
+            self.submission.submission_date,
+            &self.fingerprint()?[..8]
+        ))
+    }
+
+    /// Converts the metadata to a JSON value, as used for comparing and canonicalizing documents.
+    ///
+    /// Metadata consists of JSON compatible types only, so this is not expected to fail. As this
+    /// is not enforced by the types, e.g. a map with non-string keys could be added, the error
+    /// is returned to the caller instead of panicking.
+    pub(crate) fn to_value(&self) -> Result<serde_json::Value, SerdeError> {
+        serde_json::to_value(self).map_err(|err| SerdeError(err.to_string()))
+    }
+}
+
+impl LabDatum {
+    /// Returns the authoritative tumor cell count: determined by pathology if available,
+    /// otherwise by bioinformatics, other or unknown methods in this order.
+    pub fn authoritative_tumor_cell_count(&self) -> Option<&TumorCellCount> {
+        let rank = |method: &Method| match method {
+            Method::Pathology => 0,
+            Method::Bioinformatics => 1,
+            Method::Other => 2,
+            Method::Unknown => 3,
+        };
+        self.tumor_cell_count
+            .iter()
+            .flatten()
+            .min_by_key(|tumor_cell_count| rank(&tumor_cell_count.method))
+    }
+}
+
+/// Returns the name of an enum value as used in the metadata JSON, e.g. 'germline' for
+/// `SequenceSubtype::Germline`, or '?' if the value is not serialized as a string.
+pub fn value_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => "?".to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -59,4 +180,54 @@ mod tests {
         let data = Metadata::from_str(MTB_JSON);
         assert!(data.is_ok())
     }
+
+    #[test]
+    fn should_create_fingerprint_independent_of_field_order() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let reordered = format!(
+            "{{\"submission\": {}, \"donors\": {}}}",
+            serde_json::to_string(&metadata.submission).unwrap(),
+            serde_json::to_string(&metadata.donors).unwrap()
+        );
+        let reordered = Metadata::from_str(&reordered).unwrap();
+
+        // SHA-256 of the canonical form of the example, independently calculated by
+        // `json.dumps(value, sort_keys=True, separators=(",", ":"), ensure_ascii=False)`
+        assert_eq!(
+            metadata.fingerprint().unwrap(),
+            "a4c58a776d3c8c0a6c0932cfaaa0719a47283936e96af2dacaf886e2a9c1232e"
+        );
+        assert_eq!(
+            metadata.fingerprint().unwrap(),
+            reordered.fingerprint().unwrap()
+        );
+        assert_eq!(
+            metadata.submission_id().unwrap(),
+            reordered.submission_id().unwrap()
+        );
+
+        let mut changed = Metadata::from_str(MTB_JSON).unwrap();
+        changed.submission.lab_name = "Other lab".to_string();
+        assert_ne!(
+            metadata.fingerprint().unwrap(),
+            changed.fingerprint().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_derive_submission_id() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+
+        // Derived from the example in Python as given in the documentation of `submission_id`
+        assert_eq!(
+            metadata.submission_id().unwrap(),
+            "260914050_2024-07-15_a4c58a77"
+        );
+
+        metadata.submission.submission_date = "2024-07-16".to_string();
+        let submission_id = metadata.submission_id().unwrap();
+        assert!(submission_id.starts_with("260914050_2024-07-16_"));
+        assert_eq!(submission_id.len(), "260914050_2024-07-16_".len() + 8);
+        assert_ne!(&submission_id[21..], "a4c58a77");
+    }
 }
@@ -0,0 +1,566 @@
+//! Import of QC metrics from pipeline reports into `SequenceData`.
+//!
+//! Supported are the outputs of mosdepth (summary and thresholds), Picard CollectHsMetrics and
+//! CollectWgsMetrics, samtools stats and the general statistics of MultiQC in JSON format.
+
+use crate::files::{invalid_data, open_decompressed};
+use crate::{PercentBasesAboveQualityThreshold, SequenceData};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+/// QC metrics of sequence data, each metric is only present if it was found in the report.
+#[derive(Debug, Default, PartialEq)]
+pub struct SequenceMetrics {
+    pub mean_depth_of_coverage: Option<f64>,
+
+    /// Coverage the fraction of targeted regions refers to
+    pub min_coverage: Option<f64>,
+
+    /// Fraction of targeted bases with at least `min_coverage`
+    pub targeted_regions_above_min_coverage: Option<f64>,
+
+    /// Quality threshold the percentage of bases refers to
+    pub minimum_quality: Option<f64>,
+
+    /// Percentage of bases with at least `minimum_quality`
+    pub percent_bases_above_quality_threshold: Option<f64>,
+}
+
+impl SequenceMetrics {
+    /// Combines metrics from different reports, metrics present in `self` take precedence.
+    pub fn merge(self, other: SequenceMetrics) -> SequenceMetrics {
+        let (minimum_quality, percent_bases_above_quality_threshold) = match (
+            self.minimum_quality,
+            self.percent_bases_above_quality_threshold,
+        ) {
+            (Some(quality), Some(percent)) => (Some(quality), Some(percent)),
+            _ => (
+                other.minimum_quality,
+                other.percent_bases_above_quality_threshold,
+            ),
+        };
+        let (min_coverage, targeted_regions_above_min_coverage) =
+            match (self.min_coverage, self.targeted_regions_above_min_coverage) {
+                (Some(coverage), Some(fraction)) => (Some(coverage), Some(fraction)),
+                _ => (
+                    other.min_coverage,
+                    other.targeted_regions_above_min_coverage,
+                ),
+            };
+        SequenceMetrics {
+            mean_depth_of_coverage: self.mean_depth_of_coverage.or(other.mean_depth_of_coverage),
+            min_coverage,
+            targeted_regions_above_min_coverage,
+            minimum_quality,
+            percent_bases_above_quality_threshold,
+        }
+    }
+
+    /// Sets all metrics present to the sequence data and leaves the others unchanged.
+    ///
+    /// The coverage threshold and the fraction of targeted regions, as well as the quality
+    /// threshold and the percentage of bases, are only set together.
+    pub fn apply_to(&self, sequence_data: &mut SequenceData) {
+        if let Some(mean_depth_of_coverage) = self.mean_depth_of_coverage {
+            sequence_data.mean_depth_of_coverage = mean_depth_of_coverage;
+        }
+        if let (Some(min_coverage), Some(fraction)) =
+            (self.min_coverage, self.targeted_regions_above_min_coverage)
+        {
+            sequence_data.min_coverage = min_coverage;
+            sequence_data.targeted_regions_above_min_coverage = fraction;
+        }
+        if let (Some(minimum_quality), Some(percent)) = (
+            self.minimum_quality,
+            self.percent_bases_above_quality_threshold,
+        ) {
+            sequence_data.percent_bases_above_quality_threshold =
+                PercentBasesAboveQualityThreshold {
+                    minimum_quality,
+                    percent,
+                };
+        }
+    }
+}
+
+/// Options for reports containing metrics for several thresholds or samples.
+#[derive(Debug)]
+pub struct ImportOptions<'a> {
+    /// Coverage used for the fraction of targeted regions
+    pub min_coverage: u32,
+
+    /// Quality used for the percentage of bases above the quality threshold
+    pub minimum_quality: u8,
+
+    /// Sample to take the metrics from if the report contains more than one sample
+    pub sample: Option<&'a str>,
+}
+
+impl Default for ImportOptions<'_> {
+    fn default() -> Self {
+        ImportOptions {
+            min_coverage: 20,
+            minimum_quality: 30,
+            sample: None,
+        }
+    }
+}
+
+/// Parses a mosdepth '*.mosdepth.summary.txt' file.
+///
+/// The mean coverage of the regions is used if mosdepth was run with '--by', otherwise the mean
+/// coverage of the whole genome.
+///
+/// # Errors
+///
+/// If the data cannot be read or contains no total, an `io::Error` will be returned.
+pub fn parse_mosdepth_summary<R: BufRead>(reader: R) -> io::Result<SequenceMetrics> {
+    let mut total = None;
+    let mut total_region = None;
+
+    for line in reader.lines() {
+        let line = line?;
+        let columns = line.split('\t').collect::<Vec<_>>();
+        if let [chrom, _, _, mean, ..] = columns[..] {
+            match chrom {
+                "total" => total = Some(parse_number(mean)?),
+                "total_region" => total_region = Some(parse_number(mean)?),
+                _ => {}
+            }
+        }
+    }
+
+    match total_region.or(total) {
+        Some(mean) => Ok(SequenceMetrics {
+            mean_depth_of_coverage: Some(mean),
+            ..SequenceMetrics::default()
+        }),
+        None => Err(invalid_data("no total coverage in mosdepth summary")),
+    }
+}
+
+/// Parses a mosdepth '*.thresholds.bed' file, created with '--thresholds', and calculates the
+/// fraction of all region bases covered with at least `min_coverage`.
+///
+/// # Errors
+///
+/// If the data cannot be read or `min_coverage` is not one of the thresholds, an `io::Error`
+/// will be returned.
+pub fn parse_mosdepth_thresholds<R: BufRead>(
+    reader: R,
+    min_coverage: u32,
+) -> io::Result<SequenceMetrics> {
+    let mut lines = reader.lines();
+    let header = lines
+        .next()
+        .transpose()?
+        .ok_or_else(|| invalid_data("empty mosdepth thresholds file"))?;
+    let column = header
+        .split('\t')
+        .position(|name| name == format!("{min_coverage}X"))
+        .ok_or_else(|| {
+            invalid_data(&format!(
+                "no threshold {min_coverage}X in mosdepth thresholds file"
+            ))
+        })?;
+
+    let (mut bases, mut covered) = (0_u64, 0_u64);
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let columns = line.split('\t').collect::<Vec<_>>();
+        let (Some(start), Some(end), Some(count)) =
+            (columns.get(1), columns.get(2), columns.get(column))
+        else {
+            return Err(invalid_data("incomplete mosdepth thresholds line"));
+        };
+        let (start, end) = (parse_count(start)?, parse_count(end)?);
+        let length = end
+            .checked_sub(start)
+            .ok_or_else(|| invalid_data(&format!("region end {end} is before start {start}")))?;
+        let overflow = || invalid_data("too many bases in mosdepth thresholds file");
+        bases = bases.checked_add(length).ok_or_else(overflow)?;
+        covered = covered
+            .checked_add(parse_count(count)?)
+            .ok_or_else(overflow)?;
+    }
+
+    if bases == 0 {
+        return Err(invalid_data("no regions in mosdepth thresholds file"));
+    }
+    Ok(SequenceMetrics {
+        min_coverage: Some(min_coverage as f64),
+        targeted_regions_above_min_coverage: Some(covered as f64 / bases as f64),
+        ..SequenceMetrics::default()
+    })
+}
+
+/// Parses the metrics of Picard CollectHsMetrics or CollectWgsMetrics.
+///
+/// The mean target coverage and `PCT_TARGET_BASES_<n>X` are used for hybrid selection metrics,
+/// the mean coverage and `PCT_<n>X` for WGS metrics.
+///
+/// # Errors
+///
+/// If the data cannot be read or contains no metrics, an `io::Error` will be returned.
+pub fn parse_picard_metrics<R: BufRead>(
+    reader: R,
+    min_coverage: u32,
+) -> io::Result<SequenceMetrics> {
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next().transpose()? {
+        if !line.starts_with("## METRICS CLASS") {
+            continue;
+        }
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let values = lines.next().transpose()?.unwrap_or_default();
+        let metrics = header
+            .split('\t')
+            .zip(values.split('\t'))
+            .collect::<HashMap<_, _>>();
+
+        let metric = |names: &[String]| -> io::Result<Option<f64>> {
+            names
+                .iter()
+                .find_map(|name| metrics.get(name.as_str()))
+                .map(|value| parse_number(value))
+                .transpose()
+        };
+
+        let mean_depth_of_coverage = metric(&[
+            "MEAN_TARGET_COVERAGE".to_string(),
+            "MEAN_COVERAGE".to_string(),
+        ])?;
+        let targeted_regions_above_min_coverage = metric(&[
+            format!("PCT_TARGET_BASES_{min_coverage}X"),
+            format!("PCT_{min_coverage}X"),
+        ])?;
+        if mean_depth_of_coverage.is_none() && targeted_regions_above_min_coverage.is_none() {
+            return Err(invalid_data("no coverage metrics in Picard metrics file"));
+        }
+        return Ok(SequenceMetrics {
+            mean_depth_of_coverage,
+            min_coverage: targeted_regions_above_min_coverage.map(|_| min_coverage as f64),
+            targeted_regions_above_min_coverage,
+            ..SequenceMetrics::default()
+        });
+    }
+
+    Err(invalid_data("no metrics in Picard metrics file"))
+}
+
+/// Parses the output of samtools stats and calculates the percentage of bases with at least
+/// `minimum_quality` from the quality distribution of first and last fragments.
+///
+/// # Errors
+///
+/// If the data cannot be read or contains no quality distribution, an `io::Error` will be
+/// returned.
+pub fn parse_samtools_stats<R: BufRead>(
+    reader: R,
+    minimum_quality: u8,
+) -> io::Result<SequenceMetrics> {
+    let (mut bases, mut above) = (0, 0);
+
+    for line in reader.lines() {
+        let line = line?;
+        if !(line.starts_with("FFQ\t") || line.starts_with("LFQ\t")) {
+            continue;
+        }
+        // Columns are the section, the cycle and the counts for quality 0, 1, 2, ...
+        for (quality, count) in line.split('\t').skip(2).enumerate() {
+            let count = parse_number(count)? as u64;
+            bases += count;
+            if quality >= minimum_quality as usize {
+                above += count;
+            }
+        }
+    }
+
+    if bases == 0 {
+        return Err(invalid_data("no quality distribution in samtools stats"));
+    }
+    Ok(SequenceMetrics {
+        minimum_quality: Some(minimum_quality as f64),
+        percent_bases_above_quality_threshold: Some(100.0 * above as f64 / bases as f64),
+        ..SequenceMetrics::default()
+    })
+}
+
+/// Parses the general statistics of a MultiQC report, either 'multiqc_data.json' or
+/// 'multiqc_general_stats.json'.
+///
+/// Metrics of mosdepth, Qualimap, Picard and fastp are used. If the report contains more than one
+/// sample, the sample has to be given in the options.
+///
+/// # Errors
+///
+/// If the data cannot be read, the sample is not found or ambiguous, an `io::Error` will be
+/// returned.
+pub fn parse_multiqc_general_stats<R: BufRead>(
+    reader: R,
+    options: &ImportOptions,
+) -> io::Result<SequenceMetrics> {
+    let json: Value = serde_json::from_reader(reader)?;
+    let tables = match json.get("report_general_stats_data") {
+        Some(Value::Array(tables)) => tables.iter().collect::<Vec<_>>(),
+        _ => vec![&json],
+    };
+
+    let mut stats = HashMap::<&str, HashMap<&str, f64>>::new();
+    for table in tables.iter().filter_map(|table| table.as_object()) {
+        for (sample, values) in table {
+            let Some(values) = values.as_object() else {
+                continue;
+            };
+            let sample_stats = stats.entry(sample).or_default();
+            for (key, value) in values {
+                // Keys of 'multiqc_general_stats.json' are prefixed with the module name
+                let key = key.rsplit('-').next().unwrap_or(key);
+                if let Some(value) = value.as_f64() {
+                    sample_stats.entry(key).or_insert(value);
+                }
+            }
+        }
+    }
+
+    let sample = match options.sample {
+        Some(sample) => sample,
+        None if stats.len() == 1 => stats.keys().next().copied().unwrap_or_default(),
+        None => {
+            let mut samples = stats.keys().copied().collect::<Vec<_>>();
+            samples.sort();
+            return Err(invalid_data(&format!(
+                "MultiQC report contains samples {}, but no sample was given",
+                samples.join(", ")
+            )));
+        }
+    };
+    let Some(values) = stats.get(sample) else {
+        return Err(invalid_data(&format!(
+            "sample '{sample}' not found in MultiQC report"
+        )));
+    };
+
+    let get = |keys: &[String]| {
+        keys.iter()
+            .find_map(|key| values.get(key.as_str()).copied())
+    };
+    let min_coverage = options.min_coverage;
+    let minimum_quality = options.minimum_quality;
+
+    let mean_depth_of_coverage = get(&[
+        "mean_coverage".to_string(),
+        "MEAN_TARGET_COVERAGE".to_string(),
+        "MEAN_COVERAGE".to_string(),
+    ]);
+    // MultiQC reports the fractions of bases above a coverage as percentages
+    let targeted_regions_above_min_coverage = get(&[
+        format!("{min_coverage}_x_pc"),
+        format!("PCT_TARGET_BASES_{min_coverage}X"),
+        format!("PCT_{min_coverage}X"),
+    ])
+    .map(|percent| percent / 100.0);
+    let percent_bases_above_quality_threshold =
+        get(&[format!("after_filtering_q{minimum_quality}_rate")]).map(|rate| 100.0 * rate);
+
+    Ok(SequenceMetrics {
+        mean_depth_of_coverage,
+        min_coverage: targeted_regions_above_min_coverage.map(|_| min_coverage as f64),
+        targeted_regions_above_min_coverage,
+        minimum_quality: percent_bases_above_quality_threshold.map(|_| minimum_quality as f64),
+        percent_bases_above_quality_threshold,
+    })
+}
+
+/// Reads a report file, detecting the format from its name and content.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::metrics::{ImportOptions, read_metrics_file};
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let mut metadata = Metadata::from_str(&json).unwrap();
+///
+///     let options = ImportOptions::default();
+///     let metrics = read_metrics_file(Path::new("qc/sample.mosdepth.summary.txt"), &options)
+///         .unwrap()
+///         .merge(read_metrics_file(Path::new("qc/sample.stats"), &options).unwrap());
+///
+///     if let Some(sequence_data) = metadata.donors[0].lab_data[0].sequence_data.as_mut() {
+///         metrics.apply_to(sequence_data);
+///     }
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If the file cannot be read or its format is unknown, an `io::Error` will be returned.
+pub fn read_metrics_file(path: &Path, options: &ImportOptions) -> io::Result<SequenceMetrics> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if file_name.ends_with(".json") {
+        return parse_multiqc_general_stats(open_decompressed(path)?, options);
+    }
+    if file_name.ends_with(".mosdepth.summary.txt") {
+        return parse_mosdepth_summary(open_decompressed(path)?);
+    }
+    if file_name.contains(".thresholds.bed") {
+        return parse_mosdepth_thresholds(open_decompressed(path)?, options.min_coverage);
+    }
+
+    let mut reader = open_decompressed(path)?;
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    if first_line.starts_with("## htsjdk.samtools.metrics")
+        || first_line.starts_with("## METRICS CLASS")
+    {
+        parse_picard_metrics(open_decompressed(path)?, options.min_coverage)
+    } else if first_line.contains("samtools stats") {
+        parse_samtools_stats(open_decompressed(path)?, options.minimum_quality)
+    } else {
+        Err(invalid_data(&format!(
+            "unknown metrics file format of '{}'",
+            path.display()
+        )))
+    }
+}
+
+fn parse_number(value: &str) -> io::Result<f64> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data(&format!("invalid number '{value}'")))
+}
+
+fn parse_count(value: &str) -> io::Result<u64> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data(&format!("invalid count '{value}'")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metadata;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_parse_mosdepth_outputs() {
+        let summary = "chrom\tlength\tbases\tmean\tmin\tmax
+chr1\t248956422\t3784628976\t15.20\t0\t1850
+total\t3088269832\t46997189632\t15.22\t0\t1850
+total_region\t35000000\t3500000000\t100.00\t0\t1850
+";
+        let thresholds = "#chrom\tstart\tend\tregion\t1X\t10X\t20X
+chr1\t100\t200\tEXON1\t100\t100\t90
+chr1\t300\t400\tEXON2\t100\t80\t70
+";
+
+        let metrics = parse_mosdepth_summary(summary.as_bytes())
+            .unwrap()
+            .merge(parse_mosdepth_thresholds(thresholds.as_bytes(), 20).unwrap());
+
+        assert_eq!(
+            metrics,
+            SequenceMetrics {
+                mean_depth_of_coverage: Some(100.0),
+                min_coverage: Some(20.0),
+                targeted_regions_above_min_coverage: Some(0.8),
+                ..SequenceMetrics::default()
+            }
+        );
+        assert!(parse_mosdepth_thresholds(thresholds.as_bytes(), 30).is_err());
+    }
+
+    #[test]
+    fn should_reject_reversed_mosdepth_region() {
+        let thresholds = "#chrom\tstart\tend\tregion\t20X
+chr1\t200\t100\tEXON1\t50
+";
+
+        let err = parse_mosdepth_thresholds(thresholds.as_bytes(), 20).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn should_parse_picard_hs_metrics() {
+        let hs_metrics = "## htsjdk.samtools.metrics.StringHeader
+# CollectHsMetrics BAIT_INTERVALS=[baits.interval_list]
+
+## METRICS CLASS\tpicard.analysis.directed.HsMetrics
+BAIT_SET\tMEAN_TARGET_COVERAGE\tPCT_TARGET_BASES_10X\tPCT_TARGET_BASES_20X
+baits\t112.5\t0.98\t0.95
+
+## HISTOGRAM\tjava.lang.Integer
+";
+
+        let metrics = parse_picard_metrics(hs_metrics.as_bytes(), 20).unwrap();
+
+        assert_eq!(metrics.mean_depth_of_coverage, Some(112.5));
+        assert_eq!(metrics.targeted_regions_above_min_coverage, Some(0.95));
+    }
+
+    #[test]
+    fn should_apply_samtools_stats_and_multiqc_metrics() {
+        let stats = "# This file was produced by samtools stats (1.19)
+SN\traw total sequences:\t2
+FFQ\t1\t0\t1\t3
+LFQ\t1\t0\t0\t4
+";
+        let multiqc = r#"{
+            "report_general_stats_data": [
+                {"sample_1": {"mean_coverage": 40.5, "20_x_pc": 92.0}},
+                {"sample_1": {"after_filtering_q30_rate": 0.9}, "sample_2": {}}
+            ]
+        }"#;
+        let options = ImportOptions {
+            minimum_quality: 2,
+            sample: Some("sample_1"),
+            ..ImportOptions::default()
+        };
+
+        let metrics = parse_samtools_stats(stats.as_bytes(), options.minimum_quality)
+            .unwrap()
+            .merge(parse_multiqc_general_stats(multiqc.as_bytes(), &options).unwrap());
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let sequence_data = metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap();
+        metrics.apply_to(sequence_data);
+
+        assert_eq!(sequence_data.mean_depth_of_coverage, 40.5);
+        assert_eq!(sequence_data.min_coverage, 20.0);
+        assert_eq!(sequence_data.targeted_regions_above_min_coverage, 0.92);
+        assert_eq!(
+            sequence_data
+                .percent_bases_above_quality_threshold
+                .minimum_quality,
+            2.0
+        );
+        assert_eq!(
+            sequence_data.percent_bases_above_quality_threshold.percent,
+            87.5
+        );
+        assert!(
+            parse_multiqc_general_stats(multiqc.as_bytes(), &ImportOptions::default()).is_err()
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Range-checked numeric types for values that are plain numbers in the metadata schema.
+
+use crate::{PercentBasesAboveQualityThreshold, SequenceData, TumorCellCount};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, PartialEq)]
+pub struct RangeError(pub(crate) String);
+
+impl Display for RangeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata Range Error: {}", self.0)
+    }
+}
+
+impl Error for RangeError {}
+
+/// A percentage between 0 and 100.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Percent(f64);
+
+impl Percent {
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    pub fn to_fraction(self) -> Fraction {
+        Fraction(self.0 / 100.0)
+    }
+}
+
+impl TryFrom<f64> for Percent {
+    type Error = RangeError;
+
+    /// # Errors
+    ///
+    /// If the value is not a number between 0 and 100, a `RangeError` will be returned.
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        check_range(value, 0.0, 100.0).map(Percent)
+    }
+}
+
+/// A fraction between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Fraction(f64);
+
+impl Fraction {
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    pub fn to_percent(self) -> Percent {
+        Percent(self.0 * 100.0)
+    }
+}
+
+impl TryFrom<f64> for Fraction {
+    type Error = RangeError;
+
+    /// # Errors
+    ///
+    /// If the value is not a number between 0 and 1, a `RangeError` will be returned.
+    /// Percentages between 1 and 100 are reported as such.
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        check_range(value, 0.0, 1.0).map(Fraction).map_err(|err| {
+            if value > 1.0 && value <= 100.0 {
+                RangeError(format!(
+                    "{value} is not a fraction between 0 and 1, but looks like a percentage, \
+                     use {} instead",
+                    value / 100.0
+                ))
+            } else {
+                err
+            }
+        })
+    }
+}
+
+/// A finite number that is not negative, e.g. a depth of coverage or a file size.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct NonNegative(f64);
+
+impl NonNegative {
+    pub fn value(self) -> f64 {
+        self.0
+    }
+}
+
+impl TryFrom<f64> for NonNegative {
+    type Error = RangeError;
+
+    /// # Errors
+    ///
+    /// If the value is negative, infinite or NaN, a `RangeError` will be returned.
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        check_range(value, 0.0, f64::MAX).map(NonNegative)
+    }
+}
+
+impl TumorCellCount {
+    /// Returns the tumor cell count as range-checked percentage.
+    ///
+    /// # Errors
+    ///
+    /// If the count is not between 0 and 100, a `RangeError` will be returned.
+    pub fn percent(&self) -> Result<Percent, RangeError> {
+        Percent::try_from(self.count)
+    }
+}
+
+impl PercentBasesAboveQualityThreshold {
+    /// Returns the percentage of bases as range-checked percentage.
+    ///
+    /// # Errors
+    ///
+    /// If the percentage is not between 0 and 100, a `RangeError` will be returned.
+    pub fn percent(&self) -> Result<Percent, RangeError> {
+        Percent::try_from(self.percent)
+    }
+}
+
+impl SequenceData {
+    /// Returns the fraction of targeted regions above minimum coverage as range-checked fraction.
+    ///
+    /// # Errors
+    ///
+    /// If the fraction is not between 0 and 1, a `RangeError` will be returned.
+    pub fn targeted_regions_fraction(&self) -> Result<Fraction, RangeError> {
+        Fraction::try_from(self.targeted_regions_above_min_coverage)
+    }
+
+    /// Returns the mean depth of coverage as range-checked number.
+    ///
+    /// # Errors
+    ///
+    /// If the mean depth is negative or not finite, a `RangeError` will be returned.
+    pub fn mean_depth(&self) -> Result<NonNegative, RangeError> {
+        NonNegative::try_from(self.mean_depth_of_coverage)
+    }
+}
+
+fn check_range(value: f64, min: f64, max: f64) -> Result<f64, RangeError> {
+    if !value.is_finite() {
+        Err(RangeError(format!("{value} is not a finite number")))
+    } else if value < min || value > max {
+        if max == f64::MAX {
+            Err(RangeError(format!("{value} is negative")))
+        } else {
+            Err(RangeError(format!(
+                "{value} is not between {min} and {max}"
+            )))
+        }
+    } else {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_check_ranges() {
+        assert_eq!(Percent::try_from(95.4).map(Percent::value), Ok(95.4));
+        assert!(Percent::try_from(-1.0).is_err());
+        assert!(Percent::try_from(f64::NAN).is_err());
+        assert!(NonNegative::try_from(f64::INFINITY).is_err());
+        assert_eq!(
+            Fraction::try_from(96.0),
+            Err(RangeError(
+                "96 is not a fraction between 0 and 1, but looks like a percentage, use 0.96 instead"
+                    .to_string()
+            ))
+        );
+    }
+}
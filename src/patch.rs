@@ -0,0 +1,426 @@
+//! RFC 6902 JSON Patch generation and application for metadata corrections.
+
+use crate::diff::key_field;
+use crate::validation::Diagnostic;
+use crate::{Metadata, SerdeError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct PatchError(String);
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata Patch Error: {}", self.0)
+    }
+}
+
+impl Error for PatchError {}
+
+/// A single JSON Patch operation, paths are JSON pointers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+
+    Remove { path: String },
+
+    Replace { path: String, value: Value },
+
+    Move { from: String, path: String },
+
+    Copy { from: String, path: String },
+
+    Test { path: String, value: Value },
+}
+
+/// A JSON Patch document, the operations are applied in order.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct JsonPatch(pub Vec<PatchOperation>);
+
+impl FromStr for JsonPatch {
+    type Err = SerdeError;
+
+    /// Deserializes a JSON Patch document from a string of JSON text.
+    ///
+    /// # Errors
+    ///
+    /// If the conversion fails, an `SerdeError` will be returned.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(value).map_err(|err| SerdeError(err.to_string()))
+    }
+}
+
+impl JsonPatch {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Serializes the patch to pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// If the serialization fails, an `SerdeError` will be returned.
+    pub fn to_json(&self) -> Result<String, SerdeError> {
+        serde_json::to_string_pretty(self).map_err(|err| SerdeError(err.to_string()))
+    }
+}
+
+/// Creates a JSON Patch transforming `old` into `new`.
+///
+/// Like `diff::diff`, donors, lab data and files are matched by their keys, so reordered entries
+/// are moved instead of being replaced.
+///
+/// # Example
+///
+/// ```
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::patch::{apply_patch, create_patch};
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let previous = Metadata::from_str(JSON).unwrap();
+///     let mut correction = Metadata::from_str(JSON).unwrap();
+///     correction.submission.lab_name = "Other lab".to_string();
+///
+///     let patch = create_patch(&previous, &correction).unwrap();
+///     println!("{}", patch.to_json().unwrap());
+///
+///     let (patched, diagnostics) = apply_patch(&previous, &patch).unwrap();
+///     assert_eq!(patched.submission.lab_name, "Other lab");
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If one of the documents cannot be serialized, an `SerdeError` will be returned.
+pub fn create_patch(old: &Metadata, new: &Metadata) -> Result<JsonPatch, SerdeError> {
+    let mut operations = vec![];
+    diff_values(
+        &old.to_value()?,
+        &new.to_value()?,
+        "",
+        None,
+        &mut operations,
+    );
+    Ok(JsonPatch(operations))
+}
+
+/// Applies the patch to the metadata and validates the result.
+///
+/// The patch is applied atomically, if any operation fails, no changes are made.
+///
+/// # Errors
+///
+/// If an operation cannot be applied or the patched document is not valid metadata, a
+/// `PatchError` will be returned.
+pub fn apply_patch(
+    metadata: &Metadata,
+    patch: &JsonPatch,
+) -> Result<(Metadata, Vec<Diagnostic>), PatchError> {
+    let mut document = serde_json::to_value(metadata).map_err(|err| PatchError(err.to_string()))?;
+    apply(&mut document, patch)?;
+    let metadata: Metadata = serde_json::from_value(document)
+        .map_err(|err| PatchError(format!("patched document is not valid metadata: {err}")))?;
+    let diagnostics = metadata.validate();
+    Ok((metadata, diagnostics))
+}
+
+/// Applies the patch to a JSON document.
+///
+/// # Errors
+///
+/// If an operation cannot be applied, a `PatchError` will be returned and the document is left
+/// unchanged.
+pub fn apply(document: &mut Value, patch: &JsonPatch) -> Result<(), PatchError> {
+    let mut patched = document.clone();
+    for (i, operation) in patch.0.iter().enumerate() {
+        apply_operation(&mut patched, operation)
+            .map_err(|message| PatchError(format!("operation {i}: {message}")))?;
+    }
+    *document = patched;
+    Ok(())
+}
+
+fn apply_operation(document: &mut Value, operation: &PatchOperation) -> Result<(), String> {
+    match operation {
+        PatchOperation::Add { path, value } => add(document, path, value.clone()),
+        PatchOperation::Remove { path } => remove(document, path).map(|_| ()),
+        PatchOperation::Replace { path, value } => {
+            let target = document
+                .pointer_mut(path)
+                .ok_or_else(|| format!("path '{path}' does not exist"))?;
+            *target = value.clone();
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{from}/")) {
+                return Err(format!("cannot move '{from}' into itself"));
+            }
+            let value = remove(document, from)?;
+            add(document, path, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = document
+                .pointer(from)
+                .cloned()
+                .ok_or_else(|| format!("path '{from}' does not exist"))?;
+            add(document, path, value)
+        }
+        PatchOperation::Test { path, value } => match document.pointer(path) {
+            Some(actual) if actual == value => Ok(()),
+            Some(_) => Err(format!("value at '{path}' differs")),
+            None => Err(format!("path '{path}' does not exist")),
+        },
+    }
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let Some((parent, token)) = split_pointer(path) else {
+        *document = value;
+        return Ok(());
+    };
+    match document.pointer_mut(parent) {
+        Some(Value::Object(fields)) => {
+            fields.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(items)) => {
+            let index = if token == "-" {
+                items.len()
+            } else {
+                array_index(&token, items.len() + 1)?
+            };
+            items.insert(index, value);
+            Ok(())
+        }
+        _ => Err(format!("parent of '{path}' is not an object or array")),
+    }
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, String> {
+    let Some((parent, token)) = split_pointer(path) else {
+        return Err("cannot remove the whole document".to_string());
+    };
+    match document.pointer_mut(parent) {
+        Some(Value::Object(fields)) => fields
+            .remove(&token)
+            .ok_or_else(|| format!("path '{path}' does not exist")),
+        Some(Value::Array(items)) => {
+            let index = array_index(&token, items.len())?;
+            Ok(items.remove(index))
+        }
+        _ => Err(format!("path '{path}' does not exist")),
+    }
+}
+
+/// Splits a JSON pointer into the pointer of the parent and the unescaped last token. Returns
+/// `None` for the root pointer.
+fn split_pointer(path: &str) -> Option<(&str, String)> {
+    let index = path.rfind('/')?;
+    let token = path[index + 1..].replace("~1", "/").replace("~0", "~");
+    Some((&path[..index], token))
+}
+
+fn array_index(token: &str, limit: usize) -> Result<usize, String> {
+    match token.parse::<usize>() {
+        Ok(index) if index < limit && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(format!("invalid array index '{token}'")),
+    }
+}
+
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn diff_values(
+    old: &Value,
+    new: &Value,
+    path: &str,
+    name: Option<&str>,
+    operations: &mut Vec<PatchOperation>,
+) {
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            for (field, old_value) in old_fields {
+                let path = format!("{path}/{}", escape(field));
+                match new_fields.get(field) {
+                    Some(new_value) => {
+                        diff_values(old_value, new_value, &path, Some(field), operations)
+                    }
+                    None => operations.push(PatchOperation::Remove { path }),
+                }
+            }
+            for (field, new_value) in new_fields {
+                if !old_fields.contains_key(field) {
+                    operations.push(PatchOperation::Add {
+                        path: format!("{path}/{}", escape(field)),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items)) => match name.and_then(key_field) {
+            Some(key) => diff_keyed(old_items, new_items, key, path, operations),
+            None => {
+                for (i, (old_value, new_value)) in old_items.iter().zip(new_items).enumerate() {
+                    diff_values(
+                        old_value,
+                        new_value,
+                        &format!("{path}/{i}"),
+                        None,
+                        operations,
+                    );
+                }
+                for i in (new_items.len()..old_items.len()).rev() {
+                    operations.push(PatchOperation::Remove {
+                        path: format!("{path}/{i}"),
+                    });
+                }
+                for new_value in new_items.iter().skip(old_items.len()) {
+                    operations.push(PatchOperation::Add {
+                        path: format!("{path}/-"),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        },
+        _ if old != new => operations.push(PatchOperation::Replace {
+            path: path.to_string(),
+            value: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// Transforms a keyed list in three steps: entries without counterpart are removed, then the
+/// remaining entries are moved into the order of the new list while new entries are inserted,
+/// and each matched entry is compared once it is at its final position.
+fn diff_keyed(
+    old_items: &[Value],
+    new_items: &[Value],
+    key: &str,
+    path: &str,
+    operations: &mut Vec<PatchOperation>,
+) {
+    let key_of = |item: &Value| item.get(key).and_then(Value::as_str).map(str::to_string);
+
+    // Index of the matching old entry for each new entry, same keys are matched in order
+    let mut used = vec![false; old_items.len()];
+    let matches = new_items
+        .iter()
+        .map(|new_value| {
+            let position = old_items
+                .iter()
+                .enumerate()
+                .position(|(i, old_value)| !used[i] && key_of(old_value) == key_of(new_value))?;
+            used[position] = true;
+            Some(position)
+        })
+        .collect::<Vec<_>>();
+
+    for i in (0..old_items.len()).rev() {
+        if !used[i] {
+            operations.push(PatchOperation::Remove {
+                path: format!("{path}/{i}"),
+            });
+        }
+    }
+
+    // Current order of the remaining old entries, given by their original index
+    let mut current = (0..old_items.len())
+        .filter(|i| used[*i])
+        .collect::<Vec<_>>();
+    for (j, (new_value, old_index)) in new_items.iter().zip(matches).enumerate() {
+        let Some(old_index) = old_index else {
+            operations.push(PatchOperation::Add {
+                path: format!("{path}/{j}"),
+                value: new_value.clone(),
+            });
+            current.insert(j, usize::MAX);
+            continue;
+        };
+        let position = current.iter().position(|i| *i == old_index).unwrap_or(j);
+        if position != j {
+            operations.push(PatchOperation::Move {
+                from: format!("{path}/{position}"),
+                path: format!("{path}/{j}"),
+            });
+            let moved = current.remove(position);
+            current.insert(j, moved);
+        }
+        diff_values(
+            &old_items[old_index],
+            new_value,
+            &format!("{path}/{j}"),
+            None,
+            operations,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_create_and_apply_patch() {
+        let old = Metadata::from_str(MTB_JSON).unwrap();
+        let mut new = Metadata::from_str(MTB_JSON).unwrap();
+        new.donors.reverse();
+        new.donors[1].lab_data.reverse();
+        let sequence_data = new.donors[1].lab_data[0].sequence_data.as_mut().unwrap();
+        sequence_data.files.remove(0);
+        sequence_data.mean_depth_of_coverage = 300.0;
+        new.donors[0].lab_data[0].lab_data_name = "Renamed".to_string();
+        new.submission.lab_name = "Other lab".to_string();
+
+        let patch = create_patch(&old, &new).unwrap();
+        let (patched, diagnostics) = apply_patch(&old, &patch).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&patched).unwrap(),
+            serde_json::to_value(&new).unwrap()
+        );
+        assert!(diagnostics.iter().any(|d| d.code == "missing-bed-file"));
+        assert!(create_patch(&old, &old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_apply_operations_atomically() {
+        let mut document = json!({"a": [1, 2], "b/c": {"d": true}});
+        let patch = JsonPatch::from_str(
+            r#"[
+                {"op": "test", "path": "/b~1c/d", "value": true},
+                {"op": "move", "from": "/a/0", "path": "/a/-"},
+                {"op": "copy", "from": "/a", "path": "/e"},
+                {"op": "remove", "path": "/b~1c"}
+            ]"#,
+        )
+        .unwrap();
+
+        apply(&mut document, &patch).unwrap();
+        assert_eq!(document, json!({"a": [2, 1], "e": [2, 1]}));
+
+        let failing = JsonPatch(vec![
+            PatchOperation::Remove {
+                path: "/a".to_string(),
+            },
+            PatchOperation::Replace {
+                path: "/missing".to_string(),
+                value: json!(1),
+            },
+        ]);
+        assert!(apply(&mut document, &failing).is_err());
+        assert_eq!(document, json!({"a": [2, 1], "e": [2, 1]}));
+    }
+}
@@ -0,0 +1,374 @@
+//! Export of the family described by the donors to the PED format used by PLINK and GATK.
+
+use crate::validation::Diagnostic;
+use crate::{Donor, Gender, GenomicStudyType, Metadata, Relation};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, PartialEq)]
+pub struct PedigreeError(String);
+
+impl Display for PedigreeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata Pedigree Error: {}", self.0)
+    }
+}
+
+impl Error for PedigreeError {}
+
+/// Sex of an individual, coded as '1' for male, '2' for female and '0' for unknown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sex {
+    Male,
+
+    Female,
+
+    Unknown,
+}
+
+impl Sex {
+    pub fn code(&self) -> u8 {
+        match self {
+            Sex::Male => 1,
+            Sex::Female => 2,
+            Sex::Unknown => 0,
+        }
+    }
+}
+
+impl From<&Gender> for Sex {
+    fn from(gender: &Gender) -> Self {
+        match gender {
+            Gender::Male => Sex::Male,
+            Gender::Female => Sex::Female,
+            Gender::Other | Gender::Unknown => Sex::Unknown,
+        }
+    }
+}
+
+/// Affection status of an individual, coded as '2' for affected, '1' for unaffected and '0'
+/// for missing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phenotype {
+    Affected,
+
+    Unaffected,
+
+    Missing,
+}
+
+impl Phenotype {
+    pub fn code(&self) -> u8 {
+        match self {
+            Phenotype::Affected => 2,
+            Phenotype::Unaffected => 1,
+            Phenotype::Missing => 0,
+        }
+    }
+}
+
+/// A single line of a PED file.
+#[derive(Debug, PartialEq)]
+pub struct PedRecord {
+    pub family_id: String,
+
+    pub individual_id: String,
+
+    /// Individual ID of the father, `None` if not part of the submission
+    pub paternal_id: Option<String>,
+
+    /// Individual ID of the mother, `None` if not part of the submission
+    pub maternal_id: Option<String>,
+
+    pub sex: Sex,
+
+    pub phenotype: Phenotype,
+}
+
+impl Display for PedRecord {
+    /// Formats the six tab-separated PED columns, missing parents are given as '0'.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.family_id,
+            self.individual_id,
+            self.paternal_id.as_deref().unwrap_or("0"),
+            self.maternal_id.as_deref().unwrap_or("0"),
+            self.sex.code(),
+            self.phenotype.code()
+        )
+    }
+}
+
+/// Family of the index patient with one record per donor in order of the donors.
+#[derive(Debug, PartialEq)]
+pub struct Pedigree {
+    pub records: Vec<PedRecord>,
+}
+
+impl Pedigree {
+    /// Creates the pedigree of all donors of a submission.
+    ///
+    /// The family ID is the `local_case_id` and individual IDs are the donor pseudonyms. Father
+    /// and mother are parents of the index patient and of its siblings, the index patient is
+    /// parent of a child. Only the index patient is marked affected, the affection status of
+    /// other donors is not part of the metadata and therefore missing.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::Metadata;
+    /// use mv64e_grz_dto::pedigree::Pedigree;
+    /// use std::str::FromStr;
+    ///
+    /// fn main() {
+    ///     const JSON: &str = include_str!("../tests/example_metadata.json");
+    ///
+    ///     let metadata = Metadata::from_str(JSON).unwrap();
+    ///     print!("{}", Pedigree::from_metadata(&metadata).unwrap());
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the family cannot be represented in PED format, a `PedigreeError` listing all errors
+    /// of `check_pedigree` will be returned.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Pedigree, PedigreeError> {
+        let errors = check_pedigree(metadata)
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .map(|diagnostic| diagnostic.message)
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(PedigreeError(errors.join("; ")));
+        }
+
+        let family_id = &metadata.submission.local_case_id;
+        let pseudonym_of = |relation: Relation| {
+            donors_with(metadata, &relation)
+                .next()
+                .map(|(_, donor)| donor.donor_pseudonym.clone())
+        };
+        let index = donors_with(metadata, &Relation::Index)
+            .next()
+            .map(|(_, donor)| donor)
+            .expect("pedigree has been checked for an index patient");
+        let father = pseudonym_of(Relation::Father);
+        let mother = pseudonym_of(Relation::Mother);
+
+        let records = metadata
+            .donors
+            .iter()
+            .map(|donor| {
+                let (paternal_id, maternal_id) = match donor.relation {
+                    Relation::Index | Relation::Brother | Relation::Sister => {
+                        (father.clone(), mother.clone())
+                    }
+                    Relation::Child => match Sex::from(&index.gender) {
+                        Sex::Male => (Some(index.donor_pseudonym.clone()), None),
+                        Sex::Female => (None, Some(index.donor_pseudonym.clone())),
+                        Sex::Unknown => (None, None),
+                    },
+                    Relation::Father | Relation::Mother | Relation::Other => (None, None),
+                };
+                PedRecord {
+                    family_id: family_id.clone(),
+                    individual_id: donor.donor_pseudonym.clone(),
+                    paternal_id,
+                    maternal_id,
+                    sex: Sex::from(&donor.gender),
+                    phenotype: if donor.relation == Relation::Index {
+                        Phenotype::Affected
+                    } else {
+                        Phenotype::Missing
+                    },
+                }
+            })
+            .collect();
+
+        Ok(Pedigree { records })
+    }
+
+    /// Returns the record of the donor with the given pseudonym.
+    pub fn record(&self, donor_pseudonym: &str) -> Option<&PedRecord> {
+        self.records
+            .iter()
+            .find(|record| record.individual_id == donor_pseudonym)
+    }
+}
+
+impl Display for Pedigree {
+    /// Formats the pedigree as PED file content, one line per donor.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for record in &self.records {
+            writeln!(f, "{record}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether the donors of a submission can be represented as PED pedigree.
+///
+/// Errors are reported if there is not exactly one index patient, more than one father or
+/// mother, a parent of the opposite sex, a child of an index patient of unknown sex, IDs
+/// containing whitespace or if the number of donors does not match the genomic study type.
+pub fn check_pedigree(metadata: &Metadata) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let family_id = &metadata.submission.local_case_id;
+    if family_id.is_empty() || family_id.contains(char::is_whitespace) {
+        diagnostics.push(Diagnostic::error(
+            "pedigree-invalid-id",
+            "/submission/localCaseId",
+            format!("local case ID '{family_id}' is not a valid PED family ID"),
+        ));
+    }
+    for (i, donor) in metadata.donors.iter().enumerate() {
+        if donor.donor_pseudonym.is_empty() || donor.donor_pseudonym.contains(char::is_whitespace) {
+            diagnostics.push(Diagnostic::error(
+                "pedigree-invalid-id",
+                format!("/donors/{i}/donorPseudonym"),
+                format!(
+                    "donor pseudonym '{}' is not a valid PED individual ID",
+                    donor.donor_pseudonym
+                ),
+            ));
+        }
+    }
+
+    let indexes = donors_with(metadata, &Relation::Index).collect::<Vec<_>>();
+    match indexes.as_slice() {
+        [] => diagnostics.push(Diagnostic::error(
+            "pedigree-index-count",
+            "/donors",
+            "no donor is the index patient",
+        )),
+        [_] => {}
+        [_, more @ ..] => {
+            for (i, _) in more {
+                diagnostics.push(Diagnostic::error(
+                    "pedigree-index-count",
+                    format!("/donors/{i}/relation"),
+                    "more than one donor is the index patient",
+                ));
+            }
+        }
+    }
+
+    for (relation, name, sex) in [
+        (Relation::Father, "father", Sex::Male),
+        (Relation::Mother, "mother", Sex::Female),
+    ] {
+        for (n, (i, donor)) in donors_with(metadata, &relation).enumerate() {
+            if n > 0 {
+                diagnostics.push(Diagnostic::error(
+                    "pedigree-duplicate-parent",
+                    format!("/donors/{i}/relation"),
+                    format!("more than one donor is the {name} of the index patient"),
+                ));
+            }
+            match Sex::from(&donor.gender) {
+                Sex::Unknown => diagnostics.push(Diagnostic::warning(
+                    "pedigree-parent-sex",
+                    format!("/donors/{i}/gender"),
+                    format!("sex of the {name} of the index patient is unknown"),
+                )),
+                donor_sex if donor_sex != sex => diagnostics.push(Diagnostic::error(
+                    "pedigree-parent-sex",
+                    format!("/donors/{i}/gender"),
+                    format!("sex of the {name} of the index patient does not match"),
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    if let [(_, index)] = indexes.as_slice()
+        && Sex::from(&index.gender) == Sex::Unknown
+    {
+        for (i, _) in donors_with(metadata, &Relation::Child) {
+            diagnostics.push(Diagnostic::error(
+                "pedigree-unrepresentable",
+                format!("/donors/{i}/relation"),
+                "child cannot be linked to the index patient of unknown sex",
+            ));
+        }
+    }
+
+    let expected = match metadata.submission.genomic_study_type {
+        GenomicStudyType::Single => 1,
+        GenomicStudyType::Duo => 2,
+        GenomicStudyType::Trio => 3,
+    };
+    if metadata.donors.len() != expected {
+        diagnostics.push(Diagnostic::error(
+            "pedigree-study-type-mismatch",
+            "/submission/genomicStudyType",
+            format!(
+                "genomic study type requires {expected} donors, but there are {}",
+                metadata.donors.len()
+            ),
+        ));
+    }
+
+    diagnostics
+}
+
+fn donors_with<'a>(
+    metadata: &'a Metadata,
+    relation: &'a Relation,
+) -> impl Iterator<Item = (usize, &'a Donor)> {
+    metadata
+        .donors
+        .iter()
+        .enumerate()
+        .filter(move |(_, donor)| donor.relation == *relation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_export_ped() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let pedigree = Pedigree::from_metadata(&metadata).unwrap();
+
+        let index = &metadata.donors[0].donor_pseudonym;
+        let father = &metadata.donors[1].donor_pseudonym;
+        assert_eq!(
+            pedigree.to_string(),
+            format!(
+                "example_metadata\t{index}\t{father}\t0\t2\t2\nexample_metadata\t{father}\t0\t0\t1\t0\n"
+            )
+        );
+    }
+
+    #[test]
+    fn should_report_unrepresentable_trio() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        metadata.submission.genomic_study_type = GenomicStudyType::Trio;
+        metadata.donors[1].gender = Gender::Female;
+
+        let diagnostics = check_pedigree(&metadata);
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.pointer.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("pedigree-parent-sex", "/donors/1/gender"),
+                (
+                    "pedigree-study-type-mismatch",
+                    "/submission/genomicStudyType"
+                ),
+            ]
+        );
+        assert!(Pedigree::from_metadata(&metadata).is_err());
+    }
+}
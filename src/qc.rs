@@ -0,0 +1,310 @@
+//! Evaluation of the quality control metrics of `SequenceData` against minimum requirements.
+//!
+//! The metrics are those of the quality requirements of the BfArM for the Modellvorhaben
+//! Genomsequenzierung, see
+//! <https://www.bfarm.de/SharedDocs/Downloads/DE/Forschung/modellvorhaben-genomsequenzierung/Qs-durch-GRZ.pdf?__blob=publicationFile>
+//!
+//! No thresholds are bundled, the table of the required version of this document has to be given
+//! explicitly, see `QcThresholds::from_str`.
+
+use crate::validation::lab_data_with_pointer;
+use crate::{
+    DiseaseType, LabDatum, LibraryType, Metadata, PercentBasesAboveQualityThreshold,
+    SequenceSubtype, SequenceType, SerdeError,
+};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Minimum requirements for sequence data of the given library types and subtype.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct QcThreshold {
+    /// Library types the threshold applies to
+    pub library_types: Vec<LibraryType>,
+
+    /// Sequence subtype the threshold applies to
+    pub sequence_subtype: SequenceSubtype,
+
+    /// Disease types the threshold applies to, all disease types if missing
+    #[serde(default)]
+    pub disease_types: Option<Vec<DiseaseType>>,
+
+    /// Minimum mean depth of coverage
+    pub mean_depth_of_coverage: f64,
+
+    /// Minimum coverage at which the fraction of targeted regions has to be determined
+    pub min_coverage: f64,
+
+    /// Minimum fraction of targeted regions above minimum coverage
+    pub targeted_regions_above_min_coverage: f64,
+
+    /// Minimum quality and minimum percentage of bases above this quality
+    pub percent_bases_above_quality_threshold: PercentBasesAboveQualityThreshold,
+}
+
+impl QcThreshold {
+    fn applies_to(&self, lab_datum: &LabDatum, disease_type: &DiseaseType) -> bool {
+        lab_datum.sequence_type == SequenceType::Dna
+            && self.library_types.contains(&lab_datum.library_type)
+            && self.sequence_subtype == lab_datum.sequence_subtype
+            && self
+                .disease_types
+                .as_ref()
+                .is_none_or(|disease_types| disease_types.contains(disease_type))
+    }
+}
+
+/// Table of QC thresholds, the first matching threshold applies.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct QcThresholds(pub Vec<QcThreshold>);
+
+impl FromStr for QcThresholds {
+    type Err = SerdeError;
+
+    /// Deserializes a threshold table from a JSON array of thresholds with camel case field
+    /// names, e.g.
+    ///
+    /// ```json
+    /// [
+    ///   {
+    ///     "libraryTypes": ["wes", "wxs"],
+    ///     "sequenceSubtype": "somatic",
+    ///     "diseaseTypes": ["oncological"],
+    ///     "meanDepthOfCoverage": 200,
+    ///     "minCoverage": 20,
+    ///     "targetedRegionsAboveMinCoverage": 0.9,
+    ///     "percentBasesAboveQualityThreshold": { "minimumQuality": 30, "percent": 85 }
+    ///   }
+    /// ]
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the conversion fails, an `SerdeError` will be returned.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(value).map_err(|err| SerdeError(err.to_string()))
+    }
+}
+
+impl QcThresholds {
+    /// Returns the first threshold that applies to the lab datum of a case of the disease type.
+    pub fn find(&self, lab_datum: &LabDatum, disease_type: &DiseaseType) -> Option<&QcThreshold> {
+        self.0
+            .iter()
+            .find(|threshold| threshold.applies_to(lab_datum, disease_type))
+    }
+}
+
+/// Result of comparing a single metric with its threshold.
+#[derive(Debug, PartialEq)]
+pub struct QcCheck {
+    /// Name of the metric as used in the metadata, e.g. 'meanDepthOfCoverage'
+    pub metric: &'static str,
+
+    pub required: f64,
+
+    pub actual: f64,
+
+    pub passed: bool,
+}
+
+/// QC result of a single lab datum.
+#[derive(Debug)]
+pub struct QcReport {
+    /// JSON pointer to the lab datum in the metadata document
+    pub pointer: String,
+
+    pub lab_data_name: String,
+
+    /// Results of all checks, empty if no threshold applies or no sequence data is given
+    pub checks: Vec<QcCheck>,
+
+    /// Whether a threshold applies to the lab datum
+    pub evaluated: bool,
+}
+
+impl QcReport {
+    /// Returns `true` if all checks passed or the lab datum was not evaluated.
+    ///
+    /// A lab datum a threshold applies to fails if it has no sequence data, as its QC metrics
+    /// cannot be evaluated.
+    pub fn passed(&self) -> bool {
+        !self.evaluated || (!self.checks.is_empty() && self.checks.iter().all(|check| check.passed))
+    }
+}
+
+/// Evaluates the QC metrics of all lab data against the thresholds.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::qc::{QcThresholds, evaluate};
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let metadata = Metadata::from_str(JSON).unwrap();
+///     let thresholds = std::fs::read_to_string("qc_thresholds.json").unwrap();
+///     let thresholds = QcThresholds::from_str(&thresholds).unwrap();
+///     for report in evaluate(&metadata, &thresholds) {
+///         println!("{}: {}", report.lab_data_name, if report.passed() { "pass" } else { "fail" });
+///     }
+/// }
+/// ```
+pub fn evaluate(metadata: &Metadata, thresholds: &QcThresholds) -> Vec<QcReport> {
+    lab_data_with_pointer(metadata)
+        .map(|(pointer, lab_datum)| {
+            let threshold = thresholds.find(lab_datum, &metadata.submission.disease_type);
+            let checks = match (threshold, &lab_datum.sequence_data) {
+                (Some(threshold), Some(sequence_data)) => {
+                    let quality = &sequence_data.percent_bases_above_quality_threshold;
+                    let required_quality = &threshold.percent_bases_above_quality_threshold;
+                    vec![
+                        check(
+                            "meanDepthOfCoverage",
+                            threshold.mean_depth_of_coverage,
+                            sequence_data.mean_depth_of_coverage,
+                        ),
+                        check(
+                            "minCoverage",
+                            threshold.min_coverage,
+                            sequence_data.min_coverage,
+                        ),
+                        check(
+                            "targetedRegionsAboveMinCoverage",
+                            threshold.targeted_regions_above_min_coverage,
+                            sequence_data.targeted_regions_above_min_coverage,
+                        ),
+                        check(
+                            "minimumQuality",
+                            required_quality.minimum_quality,
+                            quality.minimum_quality,
+                        ),
+                        check("percent", required_quality.percent, quality.percent),
+                    ]
+                }
+                _ => vec![],
+            };
+            QcReport {
+                pointer,
+                lab_data_name: lab_datum.lab_data_name.clone(),
+                evaluated: threshold.is_some(),
+                checks,
+            }
+        })
+        .collect()
+}
+
+fn check(metric: &'static str, required: f64, actual: f64) -> QcCheck {
+    QcCheck {
+        metric,
+        required,
+        actual,
+        passed: actual >= required,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    /// Thresholds for testing only, not the values required by the BfArM
+    const THRESHOLDS: &str = r#"[
+        {
+            "libraryTypes": ["wes"],
+            "sequenceSubtype": "germline",
+            "meanDepthOfCoverage": 100,
+            "minCoverage": 20,
+            "targetedRegionsAboveMinCoverage": 0.9,
+            "percentBasesAboveQualityThreshold": { "minimumQuality": 30, "percent": 85 }
+        },
+        {
+            "libraryTypes": ["wes"],
+            "sequenceSubtype": "somatic",
+            "diseaseTypes": ["rare"],
+            "meanDepthOfCoverage": 500,
+            "minCoverage": 20,
+            "targetedRegionsAboveMinCoverage": 0.9,
+            "percentBasesAboveQualityThreshold": { "minimumQuality": 30, "percent": 85 }
+        },
+        {
+            "libraryTypes": ["wes"],
+            "sequenceSubtype": "somatic",
+            "meanDepthOfCoverage": 200,
+            "minCoverage": 20,
+            "targetedRegionsAboveMinCoverage": 0.9,
+            "percentBasesAboveQualityThreshold": { "minimumQuality": 30, "percent": 85 }
+        }
+    ]"#;
+
+    fn thresholds() -> QcThresholds {
+        QcThresholds::from_str(THRESHOLDS).unwrap()
+    }
+
+    #[test]
+    fn should_select_threshold_by_disease_type() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let thresholds = thresholds();
+        let lab_datum = &metadata.donors[0].lab_data[1];
+
+        let mean_depth = |disease_type| {
+            thresholds
+                .find(lab_datum, &disease_type)
+                .map(|threshold| threshold.mean_depth_of_coverage)
+        };
+
+        assert_eq!(mean_depth(DiseaseType::Oncological), Some(200.0));
+        assert_eq!(mean_depth(DiseaseType::Rare), Some(500.0));
+    }
+
+    #[test]
+    fn should_evaluate_example_metadata() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let sequence_data = metadata.donors[0].lab_data[1]
+            .sequence_data
+            .as_mut()
+            .unwrap();
+        sequence_data.mean_depth_of_coverage = 150.0;
+        sequence_data
+            .percent_bases_above_quality_threshold
+            .minimum_quality = 20.0;
+
+        let reports = evaluate(&metadata, &thresholds());
+
+        assert_eq!(reports.len(), 3);
+        assert!(reports.iter().all(|report| report.evaluated));
+        assert!(reports[0].passed());
+        assert!(reports[2].passed());
+        assert!(!reports[1].passed());
+        assert_eq!(
+            reports[1]
+                .checks
+                .iter()
+                .filter(|check| !check.passed)
+                .map(|check| check.metric)
+                .collect::<Vec<_>>(),
+            vec!["meanDepthOfCoverage", "minimumQuality"]
+        );
+    }
+
+    #[test]
+    fn should_fail_lab_datum_without_sequence_data() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        metadata.donors[0].lab_data[0].sequence_data = None;
+        metadata.donors[1].lab_data[0].sequence_type = SequenceType::Rna;
+
+        let reports = evaluate(&metadata, &thresholds());
+
+        assert!(reports[0].evaluated);
+        assert!(reports[0].checks.is_empty());
+        assert!(!reports[0].passed());
+        assert!(!reports[2].evaluated);
+        assert!(reports[2].passed());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::ReferenceGenome;
+use std::fmt::{Display, Formatter};
+
+/// Primary assembly contigs of GRCh37 and their lengths.
+const GRCH37_CONTIGS: [(&str, u64); 25] = [
+    ("1", 249_250_621),
+    ("2", 243_199_373),
+    ("3", 198_022_430),
+    ("4", 191_154_276),
+    ("5", 180_915_260),
+    ("6", 171_115_067),
+    ("7", 159_138_663),
+    ("8", 146_364_022),
+    ("9", 141_213_431),
+    ("10", 135_534_747),
+    ("11", 135_006_516),
+    ("12", 133_851_895),
+    ("13", 115_169_878),
+    ("14", 107_349_540),
+    ("15", 102_531_392),
+    ("16", 90_354_753),
+    ("17", 81_195_210),
+    ("18", 78_077_248),
+    ("19", 59_128_983),
+    ("20", 63_025_520),
+    ("21", 48_129_895),
+    ("22", 51_304_566),
+    ("X", 155_270_560),
+    ("Y", 59_373_566),
+    ("MT", 16_569),
+];
+
+/// Primary assembly contigs of GRCh38 and their lengths.
+const GRCH38_CONTIGS: [(&str, u64); 25] = [
+    ("1", 248_956_422),
+    ("2", 242_193_529),
+    ("3", 198_295_559),
+    ("4", 190_214_555),
+    ("5", 181_538_259),
+    ("6", 170_805_979),
+    ("7", 159_345_973),
+    ("8", 145_138_636),
+    ("9", 138_394_717),
+    ("10", 133_797_422),
+    ("11", 135_086_622),
+    ("12", 133_275_309),
+    ("13", 114_364_328),
+    ("14", 107_043_718),
+    ("15", 101_991_189),
+    ("16", 90_338_345),
+    ("17", 83_257_441),
+    ("18", 80_373_285),
+    ("19", 58_617_616),
+    ("20", 64_444_167),
+    ("21", 46_709_983),
+    ("22", 50_818_468),
+    ("X", 156_040_895),
+    ("Y", 57_227_415),
+    ("MT", 16_569),
+];
+
+impl ReferenceGenome {
+    /// Returns the name and length of all primary assembly contigs, named without 'chr' prefix.
+    pub fn primary_contigs(&self) -> &'static [(&'static str, u64)] {
+        match self {
+            ReferenceGenome::GrCh37 => &GRCH37_CONTIGS,
+            ReferenceGenome::GrCh38 => &GRCH38_CONTIGS,
+        }
+    }
+
+    /// Returns the length of a primary assembly contig, the name may use a 'chr' prefix.
+    pub fn contig_length(&self, name: &str) -> Option<u64> {
+        let name = canonical_contig_name(name);
+        self.primary_contigs()
+            .iter()
+            .find(|(contig, _)| *contig == name)
+            .map(|(_, length)| *length)
+    }
+
+    /// Determines the reference genome from contig names and lengths, e.g. taken from '@SQ'
+    /// header lines of a BAM file or '##contig' lines of a VCF file.
+    ///
+    /// Returns `None` if no or conflicting primary assembly contigs are found.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::ReferenceGenome;
+    ///
+    /// fn main() {
+    ///     let contigs = [("chr1", 248_956_422), ("chr2", 242_193_529)];
+    ///     assert_eq!(ReferenceGenome::detect(contigs), Some(ReferenceGenome::GrCh38));
+    /// }
+    /// ```
+    pub fn detect<'a, I>(contigs: I) -> Option<ReferenceGenome>
+    where
+        I: IntoIterator<Item = (&'a str, u64)>,
+    {
+        let (mut grch37, mut grch38) = (0, 0);
+        for (name, length) in contigs {
+            // Mitochondrial DNA has the same length in both assemblies
+            if canonical_contig_name(name) == "MT" {
+                continue;
+            }
+            if ReferenceGenome::GrCh37.contig_length(name) == Some(length) {
+                grch37 += 1;
+            }
+            if ReferenceGenome::GrCh38.contig_length(name) == Some(length) {
+                grch38 += 1;
+            }
+        }
+        match (grch37, grch38) {
+            (0, 0) => None,
+            (_, 0) => Some(ReferenceGenome::GrCh37),
+            (0, _) => Some(ReferenceGenome::GrCh38),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ReferenceGenome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReferenceGenome::GrCh37 => f.write_str("GRCh37"),
+            ReferenceGenome::GrCh38 => f.write_str("GRCh38"),
+        }
+    }
+}
+
+/// Returns the contig name without 'chr' prefix, 'M' is returned as 'MT'.
+pub(crate) fn canonical_contig_name(name: &str) -> &str {
+    match name.strip_prefix("chr").unwrap_or(name) {
+        "M" => "MT",
+        name => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_detect_reference_genome() {
+        assert_eq!(
+            ReferenceGenome::detect([("1", 249_250_621), ("MT", 16_569)]),
+            Some(ReferenceGenome::GrCh37)
+        );
+        assert_eq!(
+            ReferenceGenome::detect([("chrM", 16_569), ("chrX", 156_040_895)]),
+            Some(ReferenceGenome::GrCh38)
+        );
+        assert_eq!(
+            ReferenceGenome::detect([("1", 249_250_621), ("chrX", 156_040_895)]),
+            None
+        );
+        assert_eq!(ReferenceGenome::detect([("chrM", 16_569)]), None);
+    }
+}
@@ -0,0 +1,419 @@
+//! Machine-readable validation reports in JSON and SARIF 2.1 format.
+
+use crate::SerdeError;
+use crate::validation::{Diagnostic, Severity};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Position of a value in the metadata document, line and column are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Location {
+    pub line: usize,
+
+    /// Column in Unicode code points, not bytes or UTF-16 code units
+    pub column: usize,
+}
+
+/// A diagnostic along with the position of the affected value, if known.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportEntry {
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+}
+
+/// Validation report of a single metadata document.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    /// Path or URI of the metadata document, e.g. 'submission/metadata/metadata.json'
+    pub source: String,
+
+    /// `true` if there are no errors
+    pub valid: bool,
+
+    pub error_count: usize,
+
+    pub warning_count: usize,
+
+    pub diagnostics: Vec<ReportEntry>,
+}
+
+impl ValidationReport {
+    /// Creates a report for the diagnostics of the metadata document at `source`.
+    ///
+    /// If the JSON text of the document is given, the diagnostics are located by their JSON
+    /// pointer, so line and column can be reported.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::Metadata;
+    /// use mv64e_grz_dto::report::ValidationReport;
+    /// use std::str::FromStr;
+    ///
+    /// fn main() {
+    ///     const JSON: &str = include_str!("../tests/example_metadata.json");
+    ///
+    ///     let metadata = Metadata::from_str(JSON).unwrap();
+    ///     let report = ValidationReport::new("metadata.json", Some(JSON), metadata.validate());
+    ///     println!("{}", report.to_sarif().unwrap());
+    /// }
+    /// ```
+    pub fn new(source: &str, json: Option<&str>, diagnostics: Vec<Diagnostic>) -> Self {
+        let locations = json.map(pointer_locations).unwrap_or_default();
+
+        let error_count = diagnostics.iter().filter(|d| d.is_error()).count();
+        let warning_count = diagnostics.len() - error_count;
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|diagnostic| ReportEntry {
+                location: locate(&locations, &diagnostic.pointer),
+                diagnostic,
+            })
+            .collect();
+
+        ValidationReport {
+            source: source.to_string(),
+            valid: error_count == 0,
+            error_count,
+            warning_count,
+            diagnostics,
+        }
+    }
+
+    /// Serializes the report to pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// If the serialization fails, an `SerdeError` will be returned.
+    pub fn to_json(&self) -> Result<String, SerdeError> {
+        serde_json::to_string_pretty(self).map_err(|err| SerdeError(err.to_string()))
+    }
+
+    /// Serializes the report to a SARIF 2.1.0 log with a single run. Each diagnostic code is a
+    /// rule, the JSON pointer is given as logical location of each result. Columns are given in
+    /// Unicode code points, as declared by the `columnKind` of the run.
+    ///
+    /// # Errors
+    ///
+    /// If the serialization fails, an `SerdeError` will be returned.
+    pub fn to_sarif(&self) -> Result<String, SerdeError> {
+        serde_json::to_string_pretty(&self.sarif()).map_err(|err| SerdeError(err.to_string()))
+    }
+
+    fn sarif(&self) -> Value {
+        let mut rules = BTreeMap::new();
+        for entry in &self.diagnostics {
+            let index = rules.len();
+            rules.entry(entry.diagnostic.code).or_insert(index);
+        }
+        let mut rule_ids = rules.iter().collect::<Vec<_>>();
+        rule_ids.sort_by_key(|(_, index)| **index);
+
+        let results = self
+            .diagnostics
+            .iter()
+            .map(|entry| {
+                let mut physical_location = json!({
+                    "artifactLocation": { "uri": self.source }
+                });
+                if let Some(location) = entry.location {
+                    physical_location["region"] = json!({
+                        "startLine": location.line,
+                        "startColumn": location.column
+                    });
+                }
+                json!({
+                    "ruleId": entry.diagnostic.code,
+                    "ruleIndex": rules[entry.diagnostic.code],
+                    "level": match entry.diagnostic.severity {
+                        Severity::Error => "error",
+                        Severity::Warning => "warning",
+                    },
+                    "message": { "text": entry.diagnostic.message },
+                    "locations": [{
+                        "physicalLocation": physical_location,
+                        "logicalLocations": [{
+                            "fullyQualifiedName": entry.diagnostic.pointer,
+                            "kind": "member"
+                        }]
+                    }]
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "$schema": SARIF_SCHEMA,
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rule_ids
+                            .iter()
+                            .map(|(code, _)| json!({ "id": code }))
+                            .collect::<Vec<_>>()
+                    }
+                },
+                "artifacts": [{ "location": { "uri": self.source } }],
+                "columnKind": "unicodeCodePoints",
+                "results": results
+            }]
+        })
+    }
+}
+
+/// Returns the location of the value at the pointer or, if the value does not exist, of the
+/// closest existing parent.
+fn locate(locations: &HashMap<String, Location>, pointer: &str) -> Option<Location> {
+    let mut pointer = pointer;
+    loop {
+        if let Some(location) = locations.get(pointer) {
+            return Some(*location);
+        }
+        pointer = &pointer[..pointer.rfind('/')?];
+    }
+}
+
+/// Determines the start of every value in the JSON text by its JSON pointer.
+///
+/// Returns an empty map if the text is not valid JSON.
+fn pointer_locations(json: &str) -> HashMap<String, Location> {
+    let mut scanner = Scanner {
+        bytes: json.as_bytes(),
+        position: 0,
+        offsets: vec![],
+    };
+    if scanner.value(String::new()).is_none() {
+        return HashMap::new();
+    }
+
+    let line_starts = std::iter::once(0)
+        .chain(json.match_indices('\n').map(|(index, _)| index + 1))
+        .collect::<Vec<_>>();
+    scanner
+        .offsets
+        .into_iter()
+        .map(|(pointer, offset)| {
+            let line = line_starts.partition_point(|start| *start <= offset);
+            let column = json[line_starts[line - 1]..offset].chars().count() + 1;
+            (pointer, Location { line, column })
+        })
+        .collect()
+}
+
+/// Minimal JSON scanner recording the byte offset of each value.
+struct Scanner<'a> {
+    bytes: &'a [u8],
+
+    position: usize,
+
+    offsets: Vec<(String, usize)>,
+}
+
+impl Scanner<'_> {
+    fn value(&mut self, pointer: String) -> Option<()> {
+        self.whitespace();
+        self.offsets.push((pointer.clone(), self.position));
+
+        match self.bytes.get(self.position)? {
+            b'{' => {
+                self.position += 1;
+                self.whitespace();
+                if self.bytes.get(self.position) != Some(&b'}') {
+                    loop {
+                        self.whitespace();
+                        let key = self.string()?;
+                        self.whitespace();
+                        self.expect(b':')?;
+                        let key = key.replace('~', "~0").replace('/', "~1");
+                        self.value(format!("{pointer}/{key}"))?;
+                        if !self.separator(b'}')? {
+                            break;
+                        }
+                    }
+                }
+                self.expect(b'}')?;
+            }
+            b'[' => {
+                self.position += 1;
+                self.whitespace();
+                if self.bytes.get(self.position) != Some(&b']') {
+                    let mut index = 0;
+                    loop {
+                        self.value(format!("{pointer}/{index}"))?;
+                        index += 1;
+                        if !self.separator(b']')? {
+                            break;
+                        }
+                    }
+                }
+                self.expect(b']')?;
+            }
+            b'"' => {
+                self.string()?;
+            }
+            _ => {
+                let start = self.position;
+                while !matches!(
+                    self.bytes.get(self.position),
+                    None | Some(b',' | b']' | b'}' | b' ' | b'\t' | b'\r' | b'\n')
+                ) {
+                    self.position += 1;
+                }
+                // An unexpected delimiter instead of a value, e.g. in '[1}' or '{"a":}'
+                if self.position == start {
+                    return None;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Reads a string and returns its content, escape sequences other than '\"' and '\\' are
+    /// kept as they are.
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut content = vec![];
+        loop {
+            match self.bytes.get(self.position)? {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.bytes.get(self.position + 1)?;
+                    if !matches!(escaped, b'"' | b'\\') {
+                        content.push(b'\\');
+                    }
+                    content.push(escaped);
+                    self.position += 2;
+                }
+                byte => {
+                    content.push(*byte);
+                    self.position += 1;
+                }
+            }
+        }
+        self.position += 1;
+        String::from_utf8(content).ok()
+    }
+
+    /// Skips a ',' between members or elements and returns `true`, returns `false` at the given
+    /// closing byte without consuming it and `None` for anything else.
+    fn separator(&mut self, closing: u8) -> Option<bool> {
+        self.whitespace();
+        match self.bytes.get(self.position)? {
+            b',' => {
+                self.position += 1;
+                Some(true)
+            }
+            byte if *byte == closing => Some(false),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        if self.bytes.get(self.position) == Some(&byte) {
+            self.position += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(
+            self.bytes.get(self.position),
+            Some(b' ' | b'\t' | b'\r' | b'\n')
+        ) {
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_locate_json_pointers() {
+        let json = "{\n  \"a\": [1, {\"b/c\": true}],\n  \"d\": \"x\"\n}";
+        let locations = pointer_locations(json);
+
+        assert_eq!(locations[""], Location { line: 1, column: 1 });
+        assert_eq!(
+            locations["/a/1/b~1c"],
+            Location {
+                line: 2,
+                column: 20
+            }
+        );
+        assert_eq!(locations["/d"], Location { line: 3, column: 8 });
+        assert_eq!(
+            locate(&locations, "/a/1/missing"),
+            Some(Location {
+                line: 2,
+                column: 12
+            })
+        );
+
+        let locations = pointer_locations("{\"ä\": 1}");
+        assert_eq!(locations["/ä"], Location { line: 1, column: 7 });
+    }
+
+    #[test]
+    fn should_not_locate_pointers_in_malformed_json() {
+        for json in [
+            "[1}",
+            "{\"a\":}",
+            "{\"a\": [1, 2",
+            "{\"a\"",
+            "",
+            "[,]",
+            "[1 2]",
+            "{\"a\": 1,}",
+        ] {
+            assert!(pointer_locations(json).is_empty(), "{json:?}");
+        }
+
+        let report =
+            ValidationReport::new("x", Some("[1}"), vec![Diagnostic::error("a", "/0", "b")]);
+        assert_eq!(report.diagnostics[0].location, None);
+    }
+
+    #[test]
+    fn should_create_sarif_report() {
+        let diagnostics = vec![
+            Diagnostic::error(
+                "missing-vcf-file",
+                "/donors/0/labData/0/sequenceData/files",
+                "a",
+            ),
+            Diagnostic::warning("file-path-separator", "/donors/1", "b"),
+        ];
+
+        let report = ValidationReport::new("metadata.json", Some(MTB_JSON), diagnostics);
+        let sarif = report.sarif();
+
+        assert!(!report.valid);
+        assert_eq!(report.warning_count, 1);
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(sarif["runs"][0]["columnKind"], "unicodeCodePoints");
+        let results = &sarif["runs"][0]["results"];
+        assert_eq!(results[1]["ruleIndex"], 1);
+        assert_eq!(results[1]["level"], "warning");
+        assert_eq!(
+            results[0]["locations"][0]["logicalLocations"][0]["fullyQualifiedName"],
+            "/donors/0/labData/0/sequenceData/files"
+        );
+        assert!(results[0]["locations"][0]["physicalLocation"]["region"]["startLine"].is_u64());
+    }
+}
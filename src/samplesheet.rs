@@ -0,0 +1,401 @@
+//! CSV samplesheets for the nf-core/sarek and nf-core/raredisease pipelines generated from the
+//! lab data and files of a submission.
+
+use crate::pedigree::{Pedigree, Sex};
+use crate::{
+    Donor, File, FileType, LabDatum, Metadata, ReadOrder, SequenceSubtype, SequenceType,
+    SequencingLayout,
+};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+#[derive(Debug, PartialEq)]
+pub struct SamplesheetError(String);
+
+impl Display for SamplesheetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata Samplesheet Error: {}", self.0)
+    }
+}
+
+impl Error for SamplesheetError {}
+
+/// Supported analysis pipelines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pipeline {
+    /// nf-core/sarek, tumor/normal or germline variant calling from FASTQ or BAM files
+    Sarek,
+
+    /// nf-core/raredisease, germline analysis of a family from FASTQ files
+    Raredisease,
+}
+
+/// Creates the CSV samplesheet of the DNA lab data of a submission for the given pipeline.
+///
+/// Each FASTQ pair of a flow cell lane and each BAM file results in one row. File paths are
+/// prefixed by `files_dir`.
+///
+/// # Example
+///
+/// ```
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::samplesheet::{Pipeline, samplesheet};
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     const JSON: &str = include_str!("../tests/example_metadata.json");
+///
+///     let metadata = Metadata::from_str(JSON).unwrap();
+///     let csv = samplesheet(&metadata, Path::new("submission/files"), Pipeline::Sarek).unwrap();
+///     print!("{csv}");
+/// }
+/// ```
+///
+/// # Errors
+///
+/// If FASTQ files cannot be paired, the tumor/normal status of a lab datum is unknown, the
+/// family cannot be represented as pedigree or a file path is absolute or refers to a parent
+/// directory, a `SamplesheetError` will be returned.
+pub fn samplesheet(
+    metadata: &Metadata,
+    files_dir: &Path,
+    pipeline: Pipeline,
+) -> Result<String, SamplesheetError> {
+    match pipeline {
+        Pipeline::Sarek => sarek(metadata, files_dir),
+        Pipeline::Raredisease => raredisease(metadata, files_dir),
+    }
+}
+
+/// Creates a nf-core/sarek samplesheet with one patient per donor and one sample per lab datum.
+/// Sample IDs consist of donor pseudonym and lab data name, as they must be unique across
+/// patients.
+///
+/// Somatic lab data have status '1', germline lab data status '0'. BAM files are given without
+/// index, as the index is not part of the submission.
+pub fn sarek(metadata: &Metadata, files_dir: &Path) -> Result<String, SamplesheetError> {
+    let mut csv = String::from("patient,sex,status,sample,lane,fastq_1,fastq_2,bam\n");
+
+    for (donor, lab_datum) in dna_lab_data(metadata) {
+        let status = match lab_datum.sequence_subtype {
+            SequenceSubtype::Germline => "0",
+            SequenceSubtype::Somatic => "1",
+            SequenceSubtype::Other | SequenceSubtype::Unknown => {
+                return Err(SamplesheetError(format!(
+                    "tumor/normal status of lab datum '{}' is unknown",
+                    lab_datum.lab_data_name
+                )));
+            }
+        };
+        let sex = match Sex::from(&donor.gender) {
+            Sex::Male => "XY",
+            Sex::Female => "XX",
+            Sex::Unknown => "NA",
+        };
+
+        for group in read_groups(lab_datum)? {
+            let row = [
+                donor.donor_pseudonym.as_str(),
+                sex,
+                status,
+                &sample_id(&format!(
+                    "{}_{}",
+                    donor.donor_pseudonym, lab_datum.lab_data_name
+                )),
+                &group.lane,
+                &path(files_dir, group.fastq_1)?,
+                &path(files_dir, group.fastq_2)?,
+                &path(files_dir, group.bam)?,
+            ];
+            push_row(&row, &mut csv);
+        }
+    }
+
+    Ok(csv)
+}
+
+/// Creates a nf-core/raredisease samplesheet of the germline lab data, the sample ID being the
+/// donor pseudonym.
+///
+/// Sex, phenotype and parents are taken from the pedigree of the submission, see
+/// `Pedigree::from_metadata`. BAM files are not supported by the pipeline and therefore ignored.
+pub fn raredisease(metadata: &Metadata, files_dir: &Path) -> Result<String, SamplesheetError> {
+    let pedigree =
+        Pedigree::from_metadata(metadata).map_err(|err| SamplesheetError(err.to_string()))?;
+    let mut csv =
+        String::from("sample,lane,fastq_1,fastq_2,sex,phenotype,paternal_id,maternal_id,case_id\n");
+
+    for (donor, lab_datum) in dna_lab_data(metadata) {
+        if lab_datum.sequence_subtype != SequenceSubtype::Germline {
+            continue;
+        }
+        let record = pedigree
+            .record(&donor.donor_pseudonym)
+            .expect("pedigree contains all donors");
+
+        let groups = read_groups(lab_datum)?
+            .into_iter()
+            .filter(|group| group.fastq_1.is_some())
+            .collect::<Vec<_>>();
+        if groups.is_empty() {
+            return Err(SamplesheetError(format!(
+                "lab datum '{}' has no FASTQ files",
+                lab_datum.lab_data_name
+            )));
+        }
+
+        for group in groups {
+            let row = [
+                record.individual_id.as_str(),
+                &group.lane,
+                &path(files_dir, group.fastq_1)?,
+                &path(files_dir, group.fastq_2)?,
+                &record.sex.code().to_string(),
+                &record.phenotype.code().to_string(),
+                record.paternal_id.as_deref().unwrap_or("0"),
+                record.maternal_id.as_deref().unwrap_or("0"),
+                &record.family_id,
+            ];
+            push_row(&row, &mut csv);
+        }
+    }
+
+    Ok(csv)
+}
+
+/// Sequence files of a single row.
+struct ReadGroup<'a> {
+    lane: String,
+
+    fastq_1: Option<&'a File>,
+
+    fastq_2: Option<&'a File>,
+
+    bam: Option<&'a File>,
+}
+
+fn dna_lab_data(metadata: &Metadata) -> impl Iterator<Item = (&Donor, &LabDatum)> {
+    metadata.donors.iter().flat_map(|donor| {
+        donor
+            .lab_data
+            .iter()
+            .filter(|lab_datum| lab_datum.sequence_type == SequenceType::Dna)
+            .map(move |lab_datum| (donor, lab_datum))
+    })
+}
+
+/// Pairs FASTQ files by flow cell and lane, followed by one group per BAM file. Lanes are
+/// named '<flowcell>_<lane>' or numbered in order of appearance if not given.
+fn read_groups(lab_datum: &LabDatum) -> Result<Vec<ReadGroup<'_>>, SamplesheetError> {
+    let Some(sequence_data) = &lab_datum.sequence_data else {
+        return Ok(vec![]);
+    };
+    let paired = lab_datum.sequencing_layout == SequencingLayout::PairedEnd;
+
+    // Flow cell and lane of FASTQ files, `None` for BAM files
+    type Lane<'a> = Option<(Option<&'a str>, Option<&'a str>)>;
+    let mut groups: Vec<(Lane, ReadGroup)> = vec![];
+    for file in &sequence_data.files {
+        match file.file_type {
+            FileType::Fastq => {
+                let lane = (file.flowcell_id.as_deref(), file.lane_id.as_deref());
+                let index = match groups.iter().position(|(known, _)| *known == Some(lane)) {
+                    Some(index) => index,
+                    None => {
+                        let name = match lane {
+                            (Some(flowcell_id), Some(lane_id)) => {
+                                format!("{flowcell_id}_{lane_id}")
+                            }
+                            (_, Some(lane_id)) => lane_id.to_string(),
+                            _ => (groups.len() + 1).to_string(),
+                        };
+                        groups.push((
+                            Some(lane),
+                            ReadGroup {
+                                lane: name,
+                                fastq_1: None,
+                                fastq_2: None,
+                                bam: None,
+                            },
+                        ));
+                        groups.len() - 1
+                    }
+                };
+                let group = &mut groups[index].1;
+                let slot = match (&file.read_order, paired) {
+                    (Some(ReadOrder::R2), true) => &mut group.fastq_2,
+                    (Some(ReadOrder::R1), _) | (None, false) => &mut group.fastq_1,
+                    _ => {
+                        return Err(SamplesheetError(format!(
+                            "'{}' has no usable read order",
+                            file.file_path
+                        )));
+                    }
+                };
+                if slot.replace(file).is_some() {
+                    return Err(SamplesheetError(format!(
+                        "'{}' is an additional FASTQ file for lane '{}'",
+                        file.file_path, group.lane
+                    )));
+                }
+            }
+            FileType::Bam => {
+                let lane = (groups.len() + 1).to_string();
+                groups.push((
+                    None,
+                    ReadGroup {
+                        lane,
+                        fastq_1: None,
+                        fastq_2: None,
+                        bam: Some(file),
+                    },
+                ));
+            }
+            FileType::Bed | FileType::Vcf => {}
+        }
+    }
+
+    let groups = groups
+        .into_iter()
+        .map(|(_, group)| group)
+        .collect::<Vec<_>>();
+    for group in &groups {
+        if group.bam.is_none() && (group.fastq_1.is_none() || (paired && group.fastq_2.is_none())) {
+            return Err(SamplesheetError(format!(
+                "FASTQ files of lab datum '{}' for lane '{}' are incomplete",
+                lab_datum.lab_data_name, group.lane
+            )));
+        }
+    }
+    Ok(groups)
+}
+
+/// Replaces characters not allowed in sample IDs by '_'.
+fn sample_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Returns the path of the file within the files directory, rejecting absolute paths and paths
+/// referring to a parent directory.
+fn path(files_dir: &Path, file: Option<&File>) -> Result<String, SamplesheetError> {
+    let Some(file) = file else {
+        return Ok(String::new());
+    };
+    let path = file
+        .relative_file_path()
+        .map_err(|err| SamplesheetError(err.to_string()))?;
+    Ok(path.to_path(files_dir).display().to_string())
+}
+
+fn push_row(fields: &[&str], csv: &mut String) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::fastq;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    #[test]
+    fn should_create_sarek_samplesheet() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+
+        let csv = sarek(&metadata, Path::new("files")).unwrap();
+        let index = &metadata.donors[0].donor_pseudonym;
+
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[2],
+            format!(
+                "{index},XX,1,{index}_Blut_DNA_Tumor,1,,,files/aaaaaaaa00000000aaaaaaaa00000001.bam"
+            )
+        );
+    }
+
+    #[test]
+    fn should_create_raredisease_samplesheet() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        for donor in &mut metadata.donors {
+            let files = &mut donor.lab_data[0].sequence_data.as_mut().unwrap().files;
+            files.retain(|file| file.file_type == FileType::Bed);
+            files.push(fastq("a_L001_R1.fastq.gz", "L001", "R1"));
+            files.push(fastq("a_L001_R2.fastq.gz", "L001", "R2"));
+        }
+
+        let csv = raredisease(&metadata, Path::new("files")).unwrap();
+        let index = &metadata.donors[0].donor_pseudonym;
+        let father = &metadata.donors[1].donor_pseudonym;
+
+        assert_eq!(
+            csv.lines().nth(1),
+            Some(
+                format!(
+                    "{index},HKJ3VDSX3_L001,files/a_L001_R1.fastq.gz,files/a_L001_R2.fastq.gz,2,2,{father},0,example_metadata"
+                )
+                .as_str()
+            )
+        );
+        assert_eq!(csv.lines().count(), 3);
+
+        metadata.donors[1].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files
+            .pop();
+        assert!(raredisease(&metadata, Path::new("files")).is_err());
+    }
+
+    #[test]
+    fn should_reject_file_paths_outside_files_dir() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        for donor in &mut metadata.donors {
+            let files = &mut donor.lab_data[0].sequence_data.as_mut().unwrap().files;
+            files.retain(|file| file.file_type == FileType::Bed);
+            files.push(fastq("../x.fastq.gz", "L001", "R1"));
+            files.push(fastq("a_L001_R2.fastq.gz", "L001", "R2"));
+        }
+        assert!(matches!(
+            raredisease(&metadata, Path::new("files")),
+            Err(SamplesheetError(message)) if message.contains("../x.fastq.gz")
+        ));
+
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files[1]
+            .file_path = "/abs.bam".to_string();
+        assert!(matches!(
+            sarek(&metadata, Path::new("files")),
+            Err(SamplesheetError(message)) if message.contains("/abs.bam")
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+//! Fixtures shared by the unit tests of several modules.
+
+use crate::File;
+
+/// Creates a FASTQ file entry of flowcell 'HKJ3VDSX3' with the given lane and read order.
+pub(crate) fn fastq(path: &str, lane_id: &str, read_order: &str) -> File {
+    serde_json::from_value(serde_json::json!({
+        "filePath": path,
+        "fileType": "fastq",
+        "fileChecksum": "0358a9852adc88c77e7321ddfb07caf2e9911986a90ff76816be142f6f38122d",
+        "fileSizeInBytes": 1024,
+        "flowcellId": "HKJ3VDSX3",
+        "laneId": lane_id,
+        "readOrder": read_order
+    }))
+    .unwrap()
+}
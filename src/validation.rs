@@ -0,0 +1,754 @@
+//! Validation rules for `Metadata` that go beyond the structure enforced by deserialization.
+
+use crate::numeric::{NonNegative, RangeError};
+use crate::{
+    DiseaseType, File, FileType, LabDatum, LibraryType, Metadata, ReadOrder, SequenceData,
+    SequenceSubtype, SequencingLayout,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Severity of a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A single finding of a validation rule.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// Identifier of the rule, e.g. 'unsafe-file-path'
+    pub code: &'static str,
+
+    /// JSON pointer to the affected value in the metadata document, e.g.
+    /// '/donors/0/labData/1/sequenceData/files/0/filePath'
+    pub pointer: String,
+
+    /// Human-readable description of the finding
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(
+        code: &'static str,
+        pointer: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            pointer: pointer.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(
+        code: &'static str,
+        pointer: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code,
+            pointer: pointer.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}[{}] {}: {}",
+            self.severity, self.code, self.pointer, self.message
+        )
+    }
+}
+
+impl Metadata {
+    /// Validates the metadata and returns all diagnostics found.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mv64e_grz_dto::Metadata;
+    /// use std::str::FromStr;
+    ///
+    /// fn main() {
+    ///     const JSON: &str = include_str!("../tests/example_metadata.json");
+    ///
+    ///     let metadata = Metadata::from_str(JSON).unwrap();
+    ///     for diagnostic in metadata.validate() {
+    ///         println!("{}", diagnostic);
+    ///     }
+    /// }
+    /// ```
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        check_file_paths(self, &mut diagnostics);
+        check_file_sets(self, &mut diagnostics);
+        check_numeric_ranges(self, &mut diagnostics);
+        check_tumor_cell_counts(self, &mut diagnostics);
+        diagnostics
+    }
+}
+
+/// Iterates over all lab data of the metadata along with the JSON pointer to each `LabDatum`.
+pub(crate) fn lab_data_with_pointer(
+    metadata: &Metadata,
+) -> impl Iterator<Item = (String, &LabDatum)> {
+    metadata.donors.iter().enumerate().flat_map(|(d, donor)| {
+        donor
+            .lab_data
+            .iter()
+            .enumerate()
+            .map(move |(l, lab_datum)| (format!("/donors/{d}/labData/{l}"), lab_datum))
+    })
+}
+
+/// Iterates over all sequence data of the metadata along with the JSON pointer to each
+/// `SequenceData` entry.
+pub(crate) fn sequence_data_with_pointer(
+    metadata: &Metadata,
+) -> impl Iterator<Item = (String, &LabDatum, &SequenceData)> {
+    lab_data_with_pointer(metadata).filter_map(|(pointer, lab_datum)| {
+        lab_datum
+            .sequence_data
+            .as_ref()
+            .map(|sequence_data| (format!("{pointer}/sequenceData"), lab_datum, sequence_data))
+    })
+}
+
+/// Iterates over all files of the metadata along with the JSON pointer to each `File` entry.
+pub(crate) fn files_with_pointer(metadata: &Metadata) -> impl Iterator<Item = (String, &File)> {
+    sequence_data_with_pointer(metadata).flat_map(|(pointer, _, sequence_data)| {
+        sequence_data
+            .files
+            .iter()
+            .enumerate()
+            .map(move |(f, file)| (format!("{pointer}/files/{f}"), file))
+    })
+}
+
+/// File paths must be safe relative paths and each data file must only be referenced once.
+///
+/// A BED file may be shared between lab data as long as all entries referencing it agree on
+/// type, size and checksum.
+fn check_file_paths(metadata: &Metadata, diagnostics: &mut Vec<Diagnostic>) {
+    let mut seen = HashMap::<String, (String, &File)>::new();
+
+    for (pointer, file) in files_with_pointer(metadata) {
+        let pointer = format!("{pointer}/filePath");
+        let path = match file.relative_file_path() {
+            Ok(path) => path,
+            Err(err) => {
+                diagnostics.push(Diagnostic::error(
+                    "unsafe-file-path",
+                    pointer,
+                    err.to_string(),
+                ));
+                continue;
+            }
+        };
+
+        if file.file_path.contains('\\') {
+            diagnostics.push(Diagnostic::warning(
+                "file-path-separator",
+                &pointer,
+                format!(
+                    "'{}' uses backslashes as path separator, use '{}' instead",
+                    file.file_path, path
+                ),
+            ));
+        } else if file.file_path != path.as_str() {
+            diagnostics.push(Diagnostic::warning(
+                "file-path-not-normalized",
+                &pointer,
+                format!("'{}' should be written as '{}'", file.file_path, path),
+            ));
+        }
+
+        match seen.get(path.as_str()) {
+            Some((first_pointer, first)) => {
+                let is_shared_bed = file.file_type == FileType::Bed
+                    && first.file_type == FileType::Bed
+                    && file.file_size_in_bytes == first.file_size_in_bytes
+                    && file
+                        .file_checksum
+                        .eq_ignore_ascii_case(&first.file_checksum);
+                if !is_shared_bed {
+                    diagnostics.push(Diagnostic::error(
+                        "duplicate-file-path",
+                        &pointer,
+                        format!("'{path}' is already referenced at {first_pointer}"),
+                    ));
+                }
+            }
+            None => {
+                seen.insert(path.as_str().to_string(), (pointer, file));
+            }
+        }
+    }
+}
+
+/// The files of each sequence data entry must match library type and sequencing layout:
+///
+/// * at most one BED file, which is required for targeted sequencing (panel, WES, WXS)
+/// * at least one FASTQ or BAM file
+/// * a VCF file accompanying BAM files
+/// * paired-end FASTQ files with matching R1 and R2 for each flowcell and lane
+/// * no R2 FASTQ files for single-end sequencing
+fn check_file_sets(metadata: &Metadata, diagnostics: &mut Vec<Diagnostic>) {
+    for (pointer, lab_datum, sequence_data) in sequence_data_with_pointer(metadata) {
+        let files = sequence_data
+            .files
+            .iter()
+            .enumerate()
+            .map(|(f, file)| (format!("{pointer}/files/{f}"), file))
+            .collect::<Vec<_>>();
+        let files_of_type = |file_type: FileType| {
+            files
+                .iter()
+                .filter(move |(_, file)| file.file_type == file_type)
+        };
+        let files_pointer = format!("{pointer}/files");
+
+        let targeted = matches!(
+            lab_datum.library_type,
+            LibraryType::Panel
+                | LibraryType::PanelLr
+                | LibraryType::Wes
+                | LibraryType::WesLr
+                | LibraryType::Wxs
+                | LibraryType::WxsLr
+        );
+        let whole_genome = matches!(
+            lab_datum.library_type,
+            LibraryType::Wgs | LibraryType::WgsLr
+        );
+        let bed_files = files_of_type(FileType::Bed).collect::<Vec<_>>();
+        if bed_files.is_empty() && targeted {
+            diagnostics.push(Diagnostic::error(
+                "missing-bed-file",
+                &files_pointer,
+                "targeted sequencing requires a BED file with the target regions",
+            ));
+        }
+        for (file_pointer, file) in bed_files.iter().skip(1) {
+            diagnostics.push(Diagnostic::error(
+                "superfluous-bed-file",
+                file_pointer,
+                format!(
+                    "'{}' is a second BED file, only one is allowed",
+                    file.file_path
+                ),
+            ));
+        }
+        if whole_genome && let Some((file_pointer, file)) = bed_files.first() {
+            diagnostics.push(Diagnostic::warning(
+                "superfluous-bed-file",
+                file_pointer,
+                format!(
+                    "'{}' is a BED file for whole genome sequencing",
+                    file.file_path
+                ),
+            ));
+        }
+
+        let has_bam = files_of_type(FileType::Bam).next().is_some();
+        let has_fastq = files_of_type(FileType::Fastq).next().is_some();
+        if !has_bam && !has_fastq {
+            diagnostics.push(Diagnostic::error(
+                "missing-sequence-file",
+                &files_pointer,
+                "no FASTQ or BAM file with sequence data",
+            ));
+        }
+        if has_bam && files_of_type(FileType::Vcf).next().is_none() {
+            diagnostics.push(Diagnostic::error(
+                "missing-vcf-file",
+                &files_pointer,
+                "BAM files must be accompanied by a VCF file",
+            ));
+        }
+
+        match lab_datum.sequencing_layout {
+            SequencingLayout::PairedEnd => {
+                check_paired_fastq_files(files_of_type(FileType::Fastq), diagnostics)
+            }
+            SequencingLayout::SingleEnd => {
+                for (file_pointer, file) in files_of_type(FileType::Fastq) {
+                    if file.read_order == Some(ReadOrder::R2) {
+                        diagnostics.push(Diagnostic::error(
+                            "superfluous-fastq-file",
+                            format!("{file_pointer}/readOrder"),
+                            format!(
+                                "'{}' is a R2 FASTQ file for single-end sequencing",
+                                file.file_path
+                            ),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_paired_fastq_files<'a>(
+    fastq_files: impl Iterator<Item = &'a (String, &'a File)>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    type Lane<'a> = (Option<&'a str>, Option<&'a str>);
+    let mut lanes = Vec::<(Lane, Vec<&(String, &File)>, Vec<&(String, &File)>)>::new();
+
+    for entry @ (file_pointer, file) in fastq_files {
+        let lane = (file.flowcell_id.as_deref(), file.lane_id.as_deref());
+        let index = match lanes.iter().position(|(l, _, _)| *l == lane) {
+            Some(index) => index,
+            None => {
+                lanes.push((lane, vec![], vec![]));
+                lanes.len() - 1
+            }
+        };
+        match file.read_order {
+            Some(ReadOrder::R1) => lanes[index].1.push(entry),
+            Some(ReadOrder::R2) => lanes[index].2.push(entry),
+            None => diagnostics.push(Diagnostic::error(
+                "missing-read-order",
+                file_pointer,
+                format!(
+                    "'{}' has no read order, which is required for paired-end sequencing",
+                    file.file_path
+                ),
+            )),
+        }
+    }
+
+    for ((flowcell_id, lane_id), r1_files, r2_files) in lanes {
+        let lane = format!(
+            "flowcell '{}' and lane '{}'",
+            flowcell_id.unwrap_or_default(),
+            lane_id.unwrap_or_default()
+        );
+        for (files, mates, mate_read_order) in [
+            (&r1_files, &r2_files, ReadOrder::R2),
+            (&r2_files, &r1_files, ReadOrder::R1),
+        ] {
+            if mates.is_empty() {
+                for (file_pointer, file) in files {
+                    diagnostics.push(Diagnostic::error(
+                        "missing-fastq-file",
+                        file_pointer.as_str(),
+                        format!(
+                            "'{}' has no matching {:?} FASTQ file for {}",
+                            file.file_path, mate_read_order, lane
+                        ),
+                    ));
+                }
+            }
+            for (file_pointer, file) in files.iter().skip(1) {
+                diagnostics.push(Diagnostic::error(
+                    "superfluous-fastq-file",
+                    file_pointer.as_str(),
+                    format!(
+                        "'{}' is an additional FASTQ file with the same read order for {}",
+                        file.file_path, lane
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Numbers must be within the range of their unit. A percentage that looks like a fraction, i.e.
+/// below 1 but not 0, is reported as warning, a fraction given as percentage, e.g. 96 instead of
+/// 0.96, as error. A percentage of exactly 1 is a valid value, e.g. a tumor cell count of 1%.
+fn check_numeric_ranges(metadata: &Metadata, diagnostics: &mut Vec<Diagnostic>) {
+    let mut percentages = vec![];
+
+    for (pointer, lab_datum) in lab_data_with_pointer(metadata) {
+        for (i, tumor_cell_count) in lab_datum.tumor_cell_count.iter().flatten().enumerate() {
+            let pointer = format!("{pointer}/tumorCellCount/{i}/count");
+            check_range(tumor_cell_count.percent(), &pointer, diagnostics);
+            percentages.push((pointer, tumor_cell_count.count));
+        }
+    }
+
+    for (pointer, _, sequence_data) in sequence_data_with_pointer(metadata) {
+        check_range(
+            sequence_data.mean_depth(),
+            &format!("{pointer}/meanDepthOfCoverage"),
+            diagnostics,
+        );
+        check_range(
+            NonNegative::try_from(sequence_data.min_coverage),
+            &format!("{pointer}/minCoverage"),
+            diagnostics,
+        );
+
+        if let Err(err) = sequence_data.targeted_regions_fraction() {
+            let fraction = sequence_data.targeted_regions_above_min_coverage;
+            let code = if fraction > 1.0 && fraction <= 100.0 {
+                "fraction-as-percent"
+            } else {
+                "value-out-of-range"
+            };
+            diagnostics.push(Diagnostic::error(
+                code,
+                format!("{pointer}/targetedRegionsAboveMinCoverage"),
+                err.0,
+            ));
+        }
+
+        let quality = &sequence_data.percent_bases_above_quality_threshold;
+        let quality_pointer = format!("{pointer}/percentBasesAboveQualityThreshold");
+        check_range(
+            NonNegative::try_from(quality.minimum_quality),
+            &format!("{quality_pointer}/minimumQuality"),
+            diagnostics,
+        );
+        check_range(
+            quality.percent(),
+            &format!("{quality_pointer}/percent"),
+            diagnostics,
+        );
+        percentages.push((format!("{quality_pointer}/percent"), quality.percent));
+
+        for (f, file) in sequence_data.files.iter().enumerate() {
+            check_range(
+                NonNegative::try_from(file.file_size_in_bytes),
+                &format!("{pointer}/files/{f}/fileSizeInBytes"),
+                diagnostics,
+            );
+            if let Some(read_length) = file.read_length
+                && read_length <= 0
+            {
+                diagnostics.push(Diagnostic::error(
+                    "value-out-of-range",
+                    format!("{pointer}/files/{f}/readLength"),
+                    format!("{read_length} is not a positive read length"),
+                ));
+            }
+        }
+    }
+
+    for (pointer, percent) in percentages {
+        if percent > 0.0 && percent < 1.0 {
+            diagnostics.push(Diagnostic::warning(
+                "percent-as-fraction",
+                pointer,
+                format!(
+                    "{percent} is a percentage between 0 and 100, but looks like a fraction, \
+                     use {} if intended",
+                    percent * 100.0
+                ),
+            ));
+        }
+    }
+}
+
+/// Somatic samples of oncological submissions require a tumor cell count, germline samples
+/// should not have one. Each method may only be used once per lab datum.
+fn check_tumor_cell_counts(metadata: &Metadata, diagnostics: &mut Vec<Diagnostic>) {
+    let oncological = metadata.submission.disease_type == DiseaseType::Oncological;
+
+    for (pointer, lab_datum) in lab_data_with_pointer(metadata) {
+        let tumor_cell_counts = lab_datum.tumor_cell_count.as_deref().unwrap_or_default();
+
+        match lab_datum.sequence_subtype {
+            SequenceSubtype::Somatic if oncological && tumor_cell_counts.is_empty() => {
+                diagnostics.push(Diagnostic::error(
+                    "missing-tumor-cell-count",
+                    &pointer,
+                    format!(
+                        "somatic lab datum '{}' of an oncological submission has no tumor cell count",
+                        lab_datum.lab_data_name
+                    ),
+                ));
+            }
+            SequenceSubtype::Germline if !tumor_cell_counts.is_empty() => {
+                diagnostics.push(Diagnostic::warning(
+                    "unexpected-tumor-cell-count",
+                    format!("{pointer}/tumorCellCount"),
+                    format!(
+                        "germline lab datum '{}' has a tumor cell count",
+                        lab_datum.lab_data_name
+                    ),
+                ));
+            }
+            _ => {}
+        }
+
+        for (i, tumor_cell_count) in tumor_cell_counts.iter().enumerate() {
+            if tumor_cell_counts[..i]
+                .iter()
+                .any(|other| other.method == tumor_cell_count.method)
+            {
+                diagnostics.push(Diagnostic::error(
+                    "duplicate-tumor-cell-count-method",
+                    format!("{pointer}/tumorCellCount/{i}/method"),
+                    "tumor cell count method is already used by a previous entry",
+                ));
+            }
+        }
+    }
+}
+
+fn check_range<T>(result: Result<T, RangeError>, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+    if let Err(err) = result {
+        diagnostics.push(Diagnostic::error("value-out-of-range", pointer, err.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::fastq;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    fn file_mut(metadata: &mut Metadata, donor: usize, lab_datum: usize, file: usize) -> &mut File {
+        &mut metadata.donors[donor].lab_data[lab_datum]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files[file]
+    }
+
+    #[test]
+    fn should_validate_example_without_file_path_diagnostics() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let diagnostics = metadata.validate();
+        assert!(
+            diagnostics
+                .iter()
+                .all(|diagnostic| !diagnostic.code.contains("file-path")),
+            "{diagnostics:?}"
+        );
+    }
+
+    #[test]
+    fn should_report_unsafe_and_duplicate_file_paths() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        file_mut(&mut metadata, 0, 0, 1).file_path = "../other/a.bam".to_string();
+        file_mut(&mut metadata, 0, 1, 1).file_path = r"sub\b.bam".to_string();
+        file_mut(&mut metadata, 1, 0, 1).file_path = "sub/b.bam".to_string();
+        file_mut(&mut metadata, 1, 0, 0).file_checksum = "other".to_string();
+
+        let diagnostics = metadata.validate();
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.code.contains("file-path"))
+                .map(|diagnostic| (diagnostic.code, diagnostic.pointer.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "unsafe-file-path",
+                    "/donors/0/labData/0/sequenceData/files/1/filePath"
+                ),
+                (
+                    "file-path-separator",
+                    "/donors/0/labData/1/sequenceData/files/1/filePath"
+                ),
+                (
+                    "duplicate-file-path",
+                    "/donors/1/labData/0/sequenceData/files/0/filePath"
+                ),
+                (
+                    "duplicate-file-path",
+                    "/donors/1/labData/0/sequenceData/files/1/filePath"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_missing_vcf_file() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+
+        let diagnostics = metadata.validate();
+
+        assert!(diagnostics.contains(&Diagnostic::error(
+            "missing-vcf-file",
+            "/donors/0/labData/0/sequenceData/files",
+            "BAM files must be accompanied by a VCF file"
+        )));
+    }
+
+    #[test]
+    fn should_report_missing_and_superfluous_files() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let files = &mut metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .files;
+        files.remove(1);
+        files.push(fastq("a_L001_R1.fastq.gz", "L001", "R1"));
+        files.push(fastq("a_L001_R2.fastq.gz", "L001", "R2"));
+        files.push(fastq("a_L002_R1.fastq.gz", "L002", "R1"));
+        files.push(fastq("b_L001_R2.fastq.gz", "L001", "R2"));
+
+        let diagnostics = metadata.validate();
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.pointer.starts_with("/donors/0/labData/0/"))
+                .map(|diagnostic| (diagnostic.code, diagnostic.pointer.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "superfluous-fastq-file",
+                    "/donors/0/labData/0/sequenceData/files/4"
+                ),
+                (
+                    "missing-fastq-file",
+                    "/donors/0/labData/0/sequenceData/files/3"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_values_out_of_range() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let sequence_data = metadata.donors[0].lab_data[0]
+            .sequence_data
+            .as_mut()
+            .unwrap();
+        sequence_data.targeted_regions_above_min_coverage = 96.0;
+        sequence_data.mean_depth_of_coverage = f64::NAN;
+        sequence_data.percent_bases_above_quality_threshold.percent = 0.954;
+        sequence_data.files[0].read_length = Some(-150);
+
+        let diagnostics = metadata.validate();
+        let codes = |prefix: &str| {
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.pointer.starts_with(prefix))
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>()
+        };
+
+        let pointer = "/donors/0/labData/0/sequenceData";
+        assert_eq!(
+            codes(&format!("{pointer}/targetedRegionsAboveMinCoverage")),
+            vec!["fraction-as-percent"]
+        );
+        assert_eq!(
+            codes(&format!("{pointer}/meanDepthOfCoverage")),
+            vec!["value-out-of-range"]
+        );
+        assert_eq!(
+            codes(&format!("{pointer}/percentBasesAboveQualityThreshold")),
+            vec!["percent-as-fraction"]
+        );
+        assert_eq!(
+            codes(&format!("{pointer}/files/0/readLength")),
+            vec!["value-out-of-range"]
+        );
+    }
+
+    #[test]
+    fn should_report_percentages_below_one_only() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        metadata.donors[0].lab_data[1].tumor_cell_count = Some(vec![
+            serde_json::from_value(serde_json::json!({"count": 1, "method": "pathology"})).unwrap(),
+            serde_json::from_value(serde_json::json!({"count": 0.8, "method": "bioinformatics"}))
+                .unwrap(),
+        ]);
+        metadata.donors[0].lab_data[1]
+            .sequence_data
+            .as_mut()
+            .unwrap()
+            .percent_bases_above_quality_threshold
+            .percent = 1.0;
+
+        let pointers = metadata
+            .validate()
+            .into_iter()
+            .filter(|diagnostic| diagnostic.code == "percent-as-fraction")
+            .map(|diagnostic| diagnostic.pointer)
+            .collect::<Vec<_>>();
+
+        assert_eq!(pointers, vec!["/donors/0/labData/1/tumorCellCount/1/count"]);
+    }
+
+    #[test]
+    fn should_report_tumor_cell_count_issues() {
+        let mut metadata = Metadata::from_str(MTB_JSON).unwrap();
+        metadata.donors[0].lab_data[0].tumor_cell_count = Some(vec![
+            serde_json::from_value(serde_json::json!({"count": 80, "method": "pathology"}))
+                .unwrap(),
+        ]);
+        metadata.donors[0].lab_data[1].tumor_cell_count = Some(vec![
+            serde_json::from_value(serde_json::json!({"count": 70, "method": "bioinformatics"}))
+                .unwrap(),
+            serde_json::from_value(serde_json::json!({"count": 60, "method": "pathology"}))
+                .unwrap(),
+            serde_json::from_value(serde_json::json!({"count": 65, "method": "bioinformatics"}))
+                .unwrap(),
+        ]);
+
+        let diagnostics = metadata
+            .validate()
+            .into_iter()
+            .filter(|diagnostic| diagnostic.code.contains("tumor-cell-count"))
+            .map(|diagnostic| (diagnostic.code, diagnostic.pointer))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    "unexpected-tumor-cell-count",
+                    "/donors/0/labData/0/tumorCellCount".to_string()
+                ),
+                (
+                    "duplicate-tumor-cell-count-method",
+                    "/donors/0/labData/1/tumorCellCount/2/method".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            metadata.donors[0].lab_data[1]
+                .authoritative_tumor_cell_count()
+                .map(|tumor_cell_count| tumor_cell_count.count),
+            Some(60.0)
+        );
+
+        metadata.donors[0].lab_data[1].tumor_cell_count = None;
+        assert!(
+            metadata
+                .validate()
+                .iter()
+                .any(|diagnostic| diagnostic.code == "missing-tumor-cell-count")
+        );
+    }
+}
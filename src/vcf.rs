@@ -0,0 +1,495 @@
+//! Inspection of VCF headers to cross-check reference genome, samples and callers given in the
+//! metadata.
+
+use crate::files::open_decompressed;
+use crate::validation::Diagnostic;
+use crate::{Donor, FileType, LabDatum, Metadata, ReferenceGenome, SequenceData, SequenceSubtype};
+use std::io::{self, BufRead};
+use std::path::Path;
+
+/// Variant callers recognized in GATK style '##...CommandLine' lines. Other programs listed
+/// there, e.g. 'FilterMutectCalls' or 'SelectVariants', only process existing calls.
+const KNOWN_CALLERS: &[&str] = &[
+    "DeepVariant",
+    "FreeBayes",
+    "HaplotypeCaller",
+    "LoFreq",
+    "Manta",
+    "Mutect2",
+    "Octopus",
+    "Platypus",
+    "Strelka",
+    "Strelka2",
+    "VarDict",
+    "VarScan",
+];
+
+/// Header of a VCF file.
+#[derive(Debug, Default, PartialEq)]
+pub struct VcfHeader {
+    /// Meta-information lines as key and value, without leading '##'
+    pub meta: Vec<(String, String)>,
+
+    /// Contigs given by '##contig' lines with name and, if given, length
+    pub contigs: Vec<(String, Option<u64>)>,
+
+    /// Sample names of the '#CHROM' header line
+    pub samples: Vec<String>,
+}
+
+impl VcfHeader {
+    /// Value of the '##reference' line
+    pub fn reference(&self) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(key, _)| key == "reference")
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Names of the variant callers that created the file, taken from '##source' lines, the IDs
+    /// of GATK style '##...CommandLine' lines of known callers and '##bcftools_callCommand'
+    /// lines.
+    pub fn sources(&self) -> Vec<&str> {
+        self.meta
+            .iter()
+            .filter_map(|(key, value)| {
+                if key == "source" {
+                    Some(value.as_str())
+                } else if key == "bcftools_callCommand" {
+                    Some("bcftools")
+                } else if key.ends_with("CommandLine") {
+                    structured_field(value, "ID").filter(|id| {
+                        KNOWN_CALLERS
+                            .iter()
+                            .any(|caller| caller.eq_ignore_ascii_case(id))
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Determines the reference genome from contig lengths or, if no contig lengths are given,
+    /// from the '##reference' line.
+    pub fn reference_genome(&self) -> Option<ReferenceGenome> {
+        let detected = ReferenceGenome::detect(
+            self.contigs
+                .iter()
+                .filter_map(|(name, length)| length.map(|length| (name.as_str(), length))),
+        );
+        if detected.is_some() || self.contigs.iter().any(|(_, length)| length.is_some()) {
+            return detected;
+        }
+
+        let reference = self.reference()?.to_ascii_lowercase();
+        if ["grch38", "hg38"]
+            .iter()
+            .any(|name| reference.contains(name))
+        {
+            Some(ReferenceGenome::GrCh38)
+        } else if ["grch37", "hg19", "b37", "hs37"]
+            .iter()
+            .any(|name| reference.contains(name))
+        {
+            Some(ReferenceGenome::GrCh37)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the header of a VCF file up to and including the '#CHROM' line.
+///
+/// # Errors
+///
+/// If the data cannot be read or has no '#CHROM' line, an `io::Error` will be returned.
+pub fn read_header<R: BufRead>(reader: R) -> io::Result<VcfHeader> {
+    let mut header = VcfHeader::default();
+
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(meta) = line.strip_prefix("##") {
+            let Some((key, value)) = meta.split_once('=') else {
+                continue;
+            };
+            if key == "contig"
+                && let Some(name) = structured_field(value, "ID")
+            {
+                let length = structured_field(value, "length").and_then(|l| l.parse().ok());
+                header.contigs.push((name.to_string(), length));
+            }
+            header.meta.push((key.to_string(), value.to_string()));
+        } else if let Some(columns) = line.strip_prefix("#CHROM") {
+            header.samples = columns
+                .split('\t')
+                .skip(9)
+                .map(|sample| sample.to_string())
+                .collect();
+            return Ok(header);
+        } else {
+            break;
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "missing '#CHROM' header line",
+    ))
+}
+
+/// Reads the header of a plain, gzip or BGZF compressed VCF file.
+///
+/// # Errors
+///
+/// If the file cannot be read or is not VCF, an `io::Error` will be returned.
+pub fn read_header_file(path: &Path) -> io::Result<VcfHeader> {
+    read_header(open_decompressed(path)?)
+}
+
+/// Compares the VCF header with the reference genome of the sequence data and the donors.
+///
+/// The VCF file is expected to be located at `pointer`, the lab datum to belong to `donor`.
+/// Samples naming another donor are reported as error, a missing sample naming `donor` as
+/// warning, as samples may be named by other identifiers, e.g. 'TUMOR' and 'NORMAL'.
+pub fn compare(
+    metadata: &Metadata,
+    donor: &Donor,
+    lab_datum: &LabDatum,
+    header: &VcfHeader,
+    pointer: &str,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let Some(sequence_data) = &lab_datum.sequence_data else {
+        return diagnostics;
+    };
+
+    match header.reference_genome() {
+        Some(detected) if detected != sequence_data.reference_genome => {
+            diagnostics.push(Diagnostic::error(
+                "vcf-reference-mismatch",
+                pointer,
+                format!(
+                    "VCF file refers to {}, but reference genome is {}",
+                    detected, sequence_data.reference_genome
+                ),
+            ))
+        }
+        Some(_) => {}
+        None => diagnostics.push(Diagnostic::warning(
+            "vcf-unknown-reference",
+            pointer,
+            "reference genome cannot be determined from the VCF header",
+        )),
+    }
+
+    let expected_samples = match lab_datum.sequence_subtype {
+        SequenceSubtype::Germline => 1..=1,
+        SequenceSubtype::Somatic => 1..=2,
+        _ => 1..=usize::MAX,
+    };
+    if header.samples.is_empty() {
+        diagnostics.push(Diagnostic::error(
+            "vcf-missing-sample",
+            pointer,
+            "VCF file has no sample columns",
+        ));
+    } else if !expected_samples.contains(&header.samples.len()) {
+        diagnostics.push(Diagnostic::warning(
+            "vcf-sample-count",
+            pointer,
+            format!(
+                "VCF file has {} sample columns, expected at most {} for {:?} sequence data",
+                header.samples.len(),
+                expected_samples.end(),
+                lab_datum.sequence_subtype
+            ),
+        ));
+    }
+
+    if !header.samples.is_empty()
+        && !header
+            .samples
+            .iter()
+            .any(|sample| sample.contains(&donor.donor_pseudonym))
+    {
+        diagnostics.push(Diagnostic::warning(
+            "vcf-sample-donor-unknown",
+            pointer,
+            format!(
+                "no sample refers to donor '{}' the VCF file belongs to",
+                donor.donor_pseudonym
+            ),
+        ));
+    }
+
+    for sample in &header.samples {
+        let other_donor = metadata.donors.iter().find(|other| {
+            other.donor_pseudonym != donor.donor_pseudonym
+                && sample.contains(&other.donor_pseudonym)
+        });
+        if let Some(other_donor) = other_donor {
+            diagnostics.push(Diagnostic::error(
+                "vcf-sample-mismatch",
+                pointer,
+                format!(
+                    "sample '{}' belongs to donor '{}' ({:?})",
+                    sample, other_donor.donor_pseudonym, other_donor.relation
+                ),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Compares the callers declared for the sequence data at `pointer` with the sources of all its
+/// VCF files.
+///
+/// Sources not declared as caller are reported as error, declared callers not found in any VCF
+/// file as warning.
+pub fn compare_callers(
+    sequence_data: &SequenceData,
+    headers: &[&VcfHeader],
+    pointer: &str,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let sources = headers
+        .iter()
+        .flat_map(|header| header.sources())
+        .collect::<Vec<_>>();
+
+    if sources.is_empty() {
+        if !headers.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                "vcf-missing-source",
+                format!("{pointer}/callerUsed"),
+                "no VCF file names its source, callers cannot be checked",
+            ));
+        }
+        return diagnostics;
+    }
+
+    for source in &sources {
+        if !sequence_data
+            .caller_used
+            .iter()
+            .any(|caller| same_program(&caller.name, source))
+        {
+            diagnostics.push(Diagnostic::error(
+                "vcf-caller-undeclared",
+                format!("{pointer}/callerUsed"),
+                format!("VCF source '{source}' is not declared as caller"),
+            ));
+        }
+    }
+
+    for (c, caller) in sequence_data.caller_used.iter().enumerate() {
+        if !sources
+            .iter()
+            .any(|source| same_program(&caller.name, source))
+        {
+            diagnostics.push(Diagnostic::warning(
+                "vcf-caller-not-found",
+                format!("{pointer}/callerUsed/{c}"),
+                format!("caller '{}' is not a source of any VCF file", caller.name),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Reads the headers of all VCF files referenced in the metadata and reports mismatches with the
+/// reference genome, the donors and the declared callers.
+///
+/// # Example
+///
+/// ```no_run
+/// use mv64e_grz_dto::Metadata;
+/// use mv64e_grz_dto::vcf::check_vcf_files;
+/// use std::path::Path;
+/// use std::str::FromStr;
+///
+/// fn main() {
+///     let json = std::fs::read_to_string("submission/metadata/metadata.json").unwrap();
+///     let metadata = Metadata::from_str(&json).unwrap();
+///
+///     for diagnostic in check_vcf_files(&metadata, Path::new("submission/files")) {
+///         println!("{}", diagnostic);
+///     }
+/// }
+/// ```
+pub fn check_vcf_files(metadata: &Metadata, files_dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for (d, donor) in metadata.donors.iter().enumerate() {
+        for (l, lab_datum) in donor.lab_data.iter().enumerate() {
+            let Some(sequence_data) = &lab_datum.sequence_data else {
+                continue;
+            };
+            let pointer = format!("/donors/{d}/labData/{l}/sequenceData");
+
+            let mut headers = vec![];
+            for (f, file) in sequence_data.files.iter().enumerate() {
+                if file.file_type != FileType::Vcf {
+                    continue;
+                }
+                let file_pointer = format!("{pointer}/files/{f}");
+                let Ok(path) = file.relative_file_path() else {
+                    continue;
+                };
+                match read_header_file(&path.to_path(files_dir)) {
+                    Ok(header) => {
+                        diagnostics.extend(compare(
+                            metadata,
+                            donor,
+                            lab_datum,
+                            &header,
+                            &file_pointer,
+                        ));
+                        headers.push(header);
+                    }
+                    Err(err) => diagnostics.push(Diagnostic::error(
+                        "vcf-unreadable",
+                        file_pointer,
+                        format!("'{}' cannot be read: {}", file.file_path, err),
+                    )),
+                }
+            }
+
+            diagnostics.extend(compare_callers(
+                sequence_data,
+                &headers.iter().collect::<Vec<_>>(),
+                &pointer,
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Returns the value of a field of a structured meta-information line like
+/// '<ID=chr1,length=248956422>'.
+fn structured_field<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let value = value.strip_prefix('<')?.strip_suffix('>')?;
+    value
+        .split(',')
+        .filter_map(|field| field.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"'))
+}
+
+/// Compares program names by equality of their normalized form, see `program_name`.
+fn same_program(a: &str, b: &str) -> bool {
+    let a = program_name(a);
+    !a.is_empty() && a == program_name(b)
+}
+
+/// Normalizes a program name by removing version tokens, case, non-alphanumeric characters and
+/// a trailing major version, e.g. 'Strelka2', 'strelka' and 'Strelka v2.9.10' become 'strelka'.
+fn program_name(name: &str) -> String {
+    let name = name
+        .split_whitespace()
+        .filter(|token| {
+            !token
+                .trim_start_matches(['v', 'V'])
+                .starts_with(|c: char| c.is_ascii_digit())
+        })
+        .flat_map(str::chars)
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<String>();
+    name.trim_end_matches(|c: char| c.is_ascii_digit())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const MTB_JSON: &str = include_str!("../tests/example_metadata.json");
+
+    const VCF_HEADER: &str = "##fileformat=VCFv4.2
+##source=strelka
+##GATKCommandLine=<ID=Mutect2,CommandLine=\"Mutect2 -R ref.fa\",Version=\"4.1.8.1\">
+##GATKCommandLine=<ID=FilterMutectCalls,CommandLine=\"FilterMutectCalls -V in.vcf\",Version=\"4.1.8.1\">
+##bcftools_viewCommand=view -f PASS in.vcf.gz
+##broken=<ID=x
+##contig=<ID=chr1,length=249250621>
+##reference=file:///references/hs37d5.fa
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tTUMOR\tbbbbbbbb11111111bbbbbbbb11111111bbbbbbbb11111111bbbbbbbb11111111
+chr1\t100\t.\tA\tC\t.\tPASS\t.\tGT\t0/1\t0/0
+";
+
+    #[test]
+    fn should_read_vcf_header() {
+        let header = read_header(VCF_HEADER.as_bytes()).unwrap();
+
+        assert_eq!(header.sources(), vec!["strelka", "Mutect2"]);
+        assert_eq!(
+            header.contigs,
+            vec![("chr1".to_string(), Some(249_250_621))]
+        );
+        assert_eq!(header.samples.len(), 2);
+        assert_eq!(header.reference_genome(), Some(ReferenceGenome::GrCh37));
+    }
+
+    #[test]
+    fn should_report_reference_sample_and_caller_mismatches() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let donor = &metadata.donors[0];
+        let lab_datum = &donor.lab_data[1];
+        let header = read_header(VCF_HEADER.as_bytes()).unwrap();
+
+        let mut diagnostics = compare(&metadata, donor, lab_datum, &header, "/vcf");
+        diagnostics.extend(compare_callers(
+            lab_datum.sequence_data.as_ref().unwrap(),
+            &[&header],
+            "/sequenceData",
+        ));
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.pointer.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("vcf-reference-mismatch", "/vcf"),
+                ("vcf-sample-donor-unknown", "/vcf"),
+                ("vcf-sample-mismatch", "/vcf"),
+                ("vcf-caller-not-found", "/sequenceData/callerUsed/2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_compare_normalized_program_names() {
+        assert!(same_program("Strelka2", "strelka"));
+        assert!(same_program("freeBayes v1.3.6", "FreeBayes"));
+        assert!(same_program("Mutect2", "mutect"));
+        assert!(!same_program("caller", "HaplotypeCaller"));
+        assert!(!same_program("GATK", "GATK-SV"));
+        assert!(!same_program("2", "v2"));
+    }
+
+    #[test]
+    fn should_accept_samples_of_owning_donor() {
+        let metadata = Metadata::from_str(MTB_JSON).unwrap();
+        let donor = &metadata.donors[0];
+        let header = VcfHeader {
+            samples: vec![format!("{}_tumor", donor.donor_pseudonym)],
+            ..VcfHeader::default()
+        };
+
+        let diagnostics = compare(&metadata, donor, &donor.lab_data[1], &header, "/vcf");
+
+        assert!(
+            diagnostics
+                .iter()
+                .all(|diagnostic| !diagnostic.code.starts_with("vcf-sample"))
+        );
+        assert_eq!(structured_field("<ID=x", "ID"), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! Integration tests of the `grz-metadata` command line tool.
+
+use mv64e_grz_dto::checksum::sha256;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+const MTB_JSON: &str = include_str!("example_metadata.json");
+
+/// The empty BGZF block terminating every BAM file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+fn grz_metadata(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_grz-metadata"))
+        .args(args)
+        .output()
+        .expect("grz-metadata can be executed")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+/// Creates a submission directory of the example metadata with a VCF file added to each lab
+/// datum, all files exist with matching size and checksum. BAM files have the given content.
+fn submission(bam: &[u8]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    let files_dir = dir.path().join("files");
+    fs::create_dir_all(dir.path().join("metadata")).unwrap();
+    fs::create_dir_all(&files_dir).unwrap();
+
+    let mut metadata = serde_json::from_str::<Value>(MTB_JSON).unwrap();
+    for donor in metadata["donors"].as_array_mut().unwrap() {
+        for lab_datum in donor["labData"].as_array_mut().unwrap() {
+            let Some(files) = lab_datum["sequenceData"]["files"].as_array_mut() else {
+                continue;
+            };
+            let bam_path = files[1]["filePath"].as_str().unwrap().to_string();
+            files.push(json!({
+                "filePath": bam_path.replace(".bam", ".vcf"),
+                "fileType": "vcf",
+                "checksumType": "sha256"
+            }));
+
+            for file in files {
+                let content = match file["fileType"].as_str().unwrap() {
+                    "bed" => b"chr1\t0\t1000\n".as_slice(),
+                    "bam" => bam,
+                    _ => b"##fileformat=VCFv4.2\n".as_slice(),
+                };
+                fs::write(files_dir.join(file["filePath"].as_str().unwrap()), content).unwrap();
+                file["fileSizeInBytes"] = json!(content.len());
+                file["fileChecksum"] = json!(sha256(content, |_| {}).unwrap());
+            }
+        }
+    }
+
+    fs::write(
+        dir.path().join("metadata").join("metadata.json"),
+        serde_json::to_string_pretty(&metadata).unwrap(),
+    )
+    .unwrap();
+    dir
+}
+
+fn example_metadata(dir: &Path) -> String {
+    let path = dir.join("metadata.json");
+    fs::write(&path, MTB_JSON).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+#[test]
+fn should_validate_as_text() {
+    let dir = TempDir::new().unwrap();
+    let output = grz_metadata(&["validate", &example_metadata(dir.path())]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("error[missing-vcf-file] /donors/0/labData/0/"));
+    assert!(stderr(&output).contains("3 errors, 0 warnings"));
+
+    let submission = submission(&BGZF_EOF);
+    let output = grz_metadata(&["validate", &submission.path().to_string_lossy()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn should_validate_as_json() {
+    let dir = TempDir::new().unwrap();
+    let output = grz_metadata(&[
+        "validate",
+        "--format",
+        "json",
+        &example_metadata(dir.path()),
+    ]);
+    let report = serde_json::from_str::<Value>(&stdout(&output)).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["errorCount"], 3);
+    assert_eq!(report["diagnostics"][0]["code"], "missing-vcf-file");
+    assert!(report["diagnostics"][0]["location"]["line"].is_u64());
+}
+
+#[test]
+fn should_validate_as_sarif() {
+    let dir = TempDir::new().unwrap();
+    let output = grz_metadata(&[
+        "validate",
+        "--format",
+        "sarif",
+        &example_metadata(dir.path()),
+    ]);
+    let sarif = serde_json::from_str::<Value>(&stdout(&output)).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(sarif["version"], "2.1.0");
+    let results = sarif["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["ruleId"], "missing-vcf-file");
+    assert_eq!(results[0]["level"], "error");
+}
+
+#[test]
+fn should_fail_on_warnings_if_strict() {
+    let submission = submission(&BGZF_EOF);
+    let path = submission.path().join("metadata").join("metadata.json");
+    let json = fs::read_to_string(&path).unwrap().replacen(
+        "\"filePath\": \"aaaaaaaa00000000aaaaaaaa00000000.vcf\"",
+        "\"filePath\": \"./aaaaaaaa00000000aaaaaaaa00000000.vcf\"",
+        1,
+    );
+    fs::write(&path, json).unwrap();
+    let path = path.to_string_lossy();
+
+    let output = grz_metadata(&["validate", &path]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("warning[file-path-not-normalized]"));
+
+    let output = grz_metadata(&["validate", "--strict", &path]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn should_check_formatting() {
+    let dir = TempDir::new().unwrap();
+    let output = grz_metadata(&["fmt", "--check", &example_metadata(dir.path())]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let submission = submission(&BGZF_EOF);
+    let path = submission.path().to_string_lossy();
+
+    let output = grz_metadata(&["fmt", "--check", &path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("is not formatted"));
+
+    let output = grz_metadata(&["fmt", "--write", &path]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grz_metadata(&["fmt", "--check", &path]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn should_diff_metadata() {
+    let dir = TempDir::new().unwrap();
+    let old = example_metadata(dir.path());
+
+    let output = grz_metadata(&["diff", &old, &old]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let submission = submission(&BGZF_EOF);
+    let new = submission.path().to_string_lossy();
+
+    let output = grz_metadata(&["diff", &old, &new]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("/sequenceData/files[aaaaaaaa00000000aaaaaaaa00000000.vcf]"));
+
+    let output = grz_metadata(&["diff", "--format", "json", &old, &new]);
+    let diff = serde_json::from_str::<Value>(&stdout(&output)).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        diff["changes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|change| change["kind"] == "added"
+                && change["newPointer"] == "/donors/0/labData/0/sequenceData/files/2")
+    );
+}
+
+#[test]
+fn should_print_pedigree() {
+    let dir = TempDir::new().unwrap();
+    let output = grz_metadata(&["ped", &example_metadata(dir.path())]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let ped = stdout(&output);
+    let lines = ped.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("example_metadata\taaaaaaaa00000000"));
+    assert!(lines[0].ends_with("\t0\t2\t2"));
+}
+
+#[test]
+fn should_print_samplesheet() {
+    let submission = submission(&BGZF_EOF);
+    let path = submission.path().to_string_lossy();
+
+    let output = grz_metadata(&["samplesheet", "--pipeline", "sarek", &path]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let csv = stdout(&output);
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines[0],
+        "patient,sex,status,sample,lane,fastq_1,fastq_2,bam"
+    );
+    assert_eq!(lines.len(), 4);
+    let bam = submission
+        .path()
+        .join("files")
+        .join("aaaaaaaa00000000aaaaaaaa00000000.bam");
+    assert!(lines[1].ends_with(&format!(",{}", bam.display())));
+
+    // There are no FASTQ files required by nf-core/raredisease
+    let output = grz_metadata(&["samplesheet", "--pipeline", "raredisease", &path]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("has no FASTQ files"));
+}
+
+#[test]
+fn should_verify_files() {
+    let submission = submission(&BGZF_EOF);
+    let cache = submission.path().join("checksums.json");
+
+    let output = grz_metadata(&[
+        "verify-files",
+        "--cache",
+        &cache.to_string_lossy(),
+        &submission.path().to_string_lossy(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let lines = stdout(&output);
+    assert_eq!(lines.lines().count(), 9);
+    assert!(lines.lines().all(|line| line.starts_with("ok     ")));
+    assert!(cache.exists());
+
+    fs::write(
+        submission
+            .path()
+            .join("files")
+            .join("aaaaaaaa00000000aaaaaaaa00000001.bam"),
+        b"modified",
+    )
+    .unwrap();
+
+    let output = grz_metadata(&["verify-files", &submission.path().to_string_lossy()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("failed aaaaaaaa00000000aaaaaaaa00000001.bam: "));
+}
+
+#[test]
+fn should_report_corrupt_files() {
+    // Size and checksum match, but the BGZF EOF marker is missing
+    let submission = submission(b"truncated");
+
+    let output = grz_metadata(&["verify-files", &submission.path().to_string_lossy()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("failed aaaaaaaa00000000aaaaaaaa00000000.bam: Corrupt("));
+}
+
+#[test]
+fn should_exit_with_code_2_on_errors() {
+    let dir = TempDir::new().unwrap();
+    let missing = dir.path().join("metadata.json");
+
+    let output = grz_metadata(&["validate", &missing.to_string_lossy()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("error: "));
+
+    // The files directory is unknown for a metadata file outside a submission directory
+    let output = grz_metadata(&["verify-files", &example_metadata(dir.path())]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("--files-dir"));
+}